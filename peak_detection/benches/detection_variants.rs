@@ -0,0 +1,131 @@
+// Detection variants on synthetic recordings: each group varies one parameter (channel count, sweep size, threshold
+// i.e. density of threshold crossings, peak sign) around 128 channels, a 10 samples sweep, a 5 MAD threshold and
+// negative peaks. The _sam and _sam2 variants only detect negative peaks.
+//
+// Criterion keeps the last run in target/criterion and reports the change against it; named baselines track results
+// over time:
+//     cargo bench -- --save-baseline main
+//     cargo bench -- --baseline main
+use std::collections::HashMap;
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use ndarray::{Array1, Array2, ArrayView1, ArrayView2};
+
+use peak_detection::bench_support::{
+    detect_peaks_mask, detect_peaks_sam, detect_peaks_sam2, detect_peaks_sliding_window, generate_synthetic_recording, grid_channel_locations,
+    mad_noise_levels, neighbours_mask_to_adjency_list, SyntheticParams,
+};
+
+const VARIANTS: [&str; 4] = ["mask", "sliding_window", "sam", "sam2"];
+const NEIGHBOURS_RADIUS_UM: f32 = 50.0;
+
+const DEFAULT_CHANNELS: usize = 128;
+const DEFAULT_SWEEP_SIZE: usize = 10;
+const DEFAULT_THRESHOLD: f32 = 5.0;
+const DEFAULT_PEAK_SIGN: &str = "neg";
+
+struct Recording {
+    traces: Array2<f32>,
+    noise_levels: Array1<f32>,
+    neighbours_mask: Array2<bool>,
+    adjency_list: Vec<Vec<usize>>,
+}
+
+impl Recording {
+    // One second at 30 kHz of a 2 columns probe with 20 um pitch, one unit per 4 channels.
+    fn new(num_channels: usize) -> Self {
+        let channel_locations = grid_channel_locations(num_channels, 2, 20.0);
+        let params = SyntheticParams {
+            duration_s: 1.0, sampling_frequency: 30000.0, num_units: num_channels / 4, firing_rate_range: (5.0, 20.0),
+            refractory_period_ms: 4.0, amplitude_range: (50.0, 200.0), amplitude_jitter: 0.1, ms_before: 1.0, ms_after: 2.0,
+            noise_level: 5.0, noise_spatial_decay_um: 30.0, noise_temporal_correlation: 0.5, drift_amplitude_um: 0.0,
+            drift_period_s: 30.0, seed: Some(42),
+        };
+        let traces = generate_synthetic_recording(&channel_locations.view(), &params).traces;
+        let noise_levels = mad_noise_levels(&traces.view());
+
+        let neighbours_mask = Array2::from_shape_fn((num_channels, num_channels), |(i, j)| {
+            let dx = channel_locations[[i, 0]] - channel_locations[[j, 0]];
+            let dy = channel_locations[[i, 1]] - channel_locations[[j, 1]];
+            (dx * dx + dy * dy).sqrt() <= NEIGHBOURS_RADIUS_UM
+        });
+        let adjency_list = neighbours_mask_to_adjency_list(&neighbours_mask.view());
+
+        Recording { traces, noise_levels, neighbours_mask, adjency_list }
+    }
+
+    fn detect(&self, variant: &str, peak_sign: &str, abs_thresholds: &ArrayView1<f32>, exclude_sweep_size: usize) -> (Vec<usize>, Vec<usize>) {
+        let traces: ArrayView2<f32> = self.traces.view();
+        match variant {
+            "mask" => detect_peaks_mask(&traces, peak_sign, abs_thresholds, exclude_sweep_size, &self.adjency_list),
+            "sliding_window" => detect_peaks_sliding_window(&traces, peak_sign, abs_thresholds, exclude_sweep_size, &self.adjency_list, false),
+            "sam" => detect_peaks_sam(&traces, peak_sign, abs_thresholds, exclude_sweep_size, &self.neighbours_mask.view()),
+            "sam2" => detect_peaks_sam2(&traces, peak_sign, abs_thresholds, exclude_sweep_size, &self.neighbours_mask.view()),
+            _ => unreachable!("unknown variant {variant}"),
+        }
+    }
+}
+
+fn supports(variant: &str, peak_sign: &str) -> bool {
+    peak_sign == "neg" || ["mask", "sliding_window"].contains(&variant)
+}
+
+struct Case {
+    num_channels: usize,
+    exclude_sweep_size: usize,
+    threshold: f32,
+    peak_sign: &'static str,
+}
+
+impl Default for Case {
+    fn default() -> Self {
+        Case { num_channels: DEFAULT_CHANNELS, exclude_sweep_size: DEFAULT_SWEEP_SIZE, threshold: DEFAULT_THRESHOLD, peak_sign: DEFAULT_PEAK_SIGN }
+    }
+}
+
+fn bench_group(c: &mut Criterion, recordings: &mut HashMap<usize, Recording>, group_name: &str, cases: Vec<(String, Case)>) {
+    let mut group = c.benchmark_group(group_name);
+    group.sample_size(10);
+
+    for (parameter, case) in cases {
+        let recording = recordings.entry(case.num_channels).or_insert_with(|| Recording::new(case.num_channels));
+        let abs_thresholds = recording.noise_levels.mapv(|noise_level| noise_level * case.threshold);
+        group.throughput(Throughput::Elements(recording.traces.len() as u64));
+
+        for variant in VARIANTS.iter().filter(|&&variant| supports(variant, case.peak_sign)) {
+            group.bench_with_input(BenchmarkId::new(*variant, &parameter), &case, |b, case| {
+                b.iter(|| black_box(recording.detect(variant, case.peak_sign, &abs_thresholds.view(), case.exclude_sweep_size)));
+            });
+        }
+    }
+    group.finish();
+}
+
+fn detection_variants(c: &mut Criterion) {
+    let mut recordings: HashMap<usize, Recording> = HashMap::new();
+
+    let cases = [32, 128, 384].into_iter()
+        .map(|num_channels| (num_channels.to_string(), Case { num_channels, ..Case::default() }))
+        .collect();
+    bench_group(c, &mut recordings, "channels", cases);
+
+    let cases = [5, 10, 20].into_iter()
+        .map(|exclude_sweep_size| (exclude_sweep_size.to_string(), Case { exclude_sweep_size, ..Case::default() }))
+        .collect();
+    bench_group(c, &mut recordings, "sweep_size", cases);
+
+    // lower thresholds mean denser threshold crossings
+    let cases = [3.0, 5.0, 8.0].into_iter()
+        .map(|threshold| (format!("{threshold}mad"), Case { threshold, ..Case::default() }))
+        .collect();
+    bench_group(c, &mut recordings, "threshold", cases);
+
+    let cases = ["neg", "pos", "both"].into_iter()
+        .map(|peak_sign| (peak_sign.to_string(), Case { peak_sign, ..Case::default() }))
+        .collect();
+    bench_group(c, &mut recordings, "peak_sign", cases);
+}
+
+criterion_group!(benches, detection_variants);
+criterion_main!(benches);
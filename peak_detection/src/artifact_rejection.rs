@@ -0,0 +1,93 @@
+use ndarray::{ArrayView1, ArrayView2};
+use numpy::{IntoPyArray, PyArray1, PyReadonlyArray1, PyReadonlyArray2};
+use pyo3::prelude::*;
+
+use crate::rust_peak_detection_locally_exclusive_sliding_window::{detect_peaks_locally_exclusive, neighbours_mask_to_adjency_list};
+
+pub(crate) struct ArtifactParams {
+    pub channel_fraction: f32,
+    pub saturation_level: Option<f32>,
+    pub exclude_size: usize,
+}
+
+#[pyfunction]
+#[pyo3(signature = (traces, peak_sign, abs_thresholds, exclude_sweep_size, neighbours_mask, artifact_thresholds, artifact_channel_fraction, saturation_level=None, artifact_exclude_size=0))]
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn detect_peaks_rust_locally_exclusive_artifact_rejection_on_chunk<'py>(py: Python<'py>, traces: PyReadonlyArray2<f32>, peak_sign: &str,
+            abs_thresholds: PyReadonlyArray1<f32>, exclude_sweep_size: usize, neighbours_mask: PyReadonlyArray2<bool>,
+            artifact_thresholds: PyReadonlyArray1<f32>, artifact_channel_fraction: f32, saturation_level: Option<f32>, artifact_exclude_size: usize)
+            -> (Bound<'py,PyArray1<usize>>, Bound<'py,PyArray1<usize>>, Bound<'py,PyArray1<usize>>, Bound<'py,PyArray1<usize>>) {
+    assert!(["pos", "neg", "both"].contains(&peak_sign), "peak_sign must be 'pos', 'neg', or 'both'");
+    assert!((0.0..=1.0).contains(&artifact_channel_fraction), "artifact_channel_fraction must be between 0 and 1");
+
+    let traces: ArrayView2<f32> = traces.as_array();
+    let abs_thresholds: ArrayView1<f32> = abs_thresholds.as_array();
+    let artifact_thresholds: ArrayView1<f32> = artifact_thresholds.as_array();
+    let neighbours_mask: ArrayView2<bool> = neighbours_mask.as_array();
+    let adjency_list: Vec<Vec<usize>> = neighbours_mask_to_adjency_list(&neighbours_mask);
+    let params = ArtifactParams { channel_fraction: artifact_channel_fraction, saturation_level, exclude_size: artifact_exclude_size };
+
+    let (peaks, intervals) = py.detach(|| {
+        let intervals = find_artifact_intervals(&traces, &artifact_thresholds, &params);
+        let peaks = detect_peaks_locally_exclusive_without_artifacts(&traces, peak_sign, &abs_thresholds, exclude_sweep_size, &adjency_list, &intervals);
+        (peaks, intervals)
+    });
+    let (starts, ends): (Vec<usize>, Vec<usize>) = intervals.into_iter().unzip();
+
+    (peaks.0.into_pyarray(py), peaks.1.into_pyarray(py), starts.into_pyarray(py), ends.into_pyarray(py))
+}
+
+// Returns the sorted, non-overlapping [start, end) sample intervals to exclude from detection.
+pub(crate) fn find_artifact_intervals(traces: &ArrayView2<f32>, artifact_thresholds: &ArrayView1<f32>, params: &ArtifactParams) -> Vec<(usize, usize)> {
+    let n_samples = traces.nrows();
+    let n_channels = traces.ncols();
+    let min_channels = params.channel_fraction * n_channels as f32;
+
+    let mut intervals: Vec<(usize, usize)> = Vec::new();
+    for (i, row) in traces.outer_iter().enumerate() {
+        let mut n_above = 0;
+        let mut saturated = false;
+        for (j, &value) in row.iter().enumerate() {
+            if value.abs() > artifact_thresholds[j] {
+                n_above += 1;
+            }
+            if params.saturation_level.is_some_and(|level| value.abs() >= level) {
+                saturated = true;
+            }
+        }
+
+        if !saturated && (n_above as f32) <= min_channels {
+            continue;
+        }
+
+        let start = i.saturating_sub(params.exclude_size);
+        let end = (i + params.exclude_size + 1).min(n_samples);
+        match intervals.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => intervals.push((start, end)),
+        }
+    }
+
+    intervals
+}
+
+// Detects on the original traces and drops the peaks in or within exclude_sweep_size of an artifact interval: a peak
+// there may be the artifact itself or have been compared against it. Blanking the artifacts instead would create steps
+// at the interval edges, and so peaks.
+pub(crate) fn detect_peaks_locally_exclusive_without_artifacts(traces: &ArrayView2<f32>, peak_sign: &str, abs_thresholds: &ArrayView1<f32>,
+    exclude_sweep_size: usize, adjency_list: &[Vec<usize>], intervals: &[(usize, usize)]) -> (Vec<usize>, Vec<usize>) {
+    let (sample_indices, channel_indices) = detect_peaks_locally_exclusive(traces, peak_sign, abs_thresholds, exclude_sweep_size, adjency_list, false);
+    if intervals.is_empty() {
+        return (sample_indices, channel_indices);
+    }
+
+    // the intervals are sorted and non-overlapping, so the first one ending after the peak is the only candidate
+    let near_artifact = |sample_ind: usize| -> bool {
+        let k = intervals.partition_point(|&(_, end)| end + exclude_sweep_size <= sample_ind);
+        k < intervals.len() && intervals[k].0 <= sample_ind + exclude_sweep_size
+    };
+
+    sample_indices.into_iter().zip(channel_indices)
+        .filter(|&(sample_ind, _)| !near_artifact(sample_ind))
+        .unzip()
+}
@@ -0,0 +1,209 @@
+use std::f64::consts::PI;
+
+use ndarray::{s, Array1, Array2, ArrayView1, ArrayView2, Axis};
+use ndarray::parallel::prelude::*;
+use numpy::{IntoPyArray, PyArray1, PyReadonlyArray1, PyReadonlyArray2};
+use pyo3::prelude::*;
+use realfft::RealFftPlanner;
+
+use crate::common_reference::median;
+use crate::noise_levels::random_chunk_starts;
+
+pub(crate) const CHANNEL_LABELS: [&str; 4] = ["good", "dead", "noise", "out"];
+const GOOD: usize = 0;
+const DEAD: usize = 1;
+const NOISE: usize = 2;
+const OUT: usize = 3;
+
+pub(crate) struct BadChannelParams {
+    pub sampling_frequency: f64,
+    pub psd_hf_threshold: f64,
+    pub dead_channel_threshold: f64,
+    pub noisy_channel_threshold: f64,
+    pub outside_channel_threshold: f64,
+    pub n_neighbors: usize,
+    pub nyquist_threshold: f64,
+    pub welch_window_size: usize,
+}
+
+#[pyfunction]
+#[pyo3(signature = (traces, sampling_frequency, channel_depths, num_random_chunks=100, chunk_duration_s=0.3, seed=None, psd_hf_threshold=0.02,
+    dead_channel_threshold=-0.5, noisy_channel_threshold=1.0, outside_channel_threshold=-0.75, n_neighbors=11, nyquist_threshold=0.8, welch_window_ms=10.0))]
+#[allow(clippy::too_many_arguments)]
+pub fn detect_bad_channels_rust<'py>(py: Python<'py>, traces: PyReadonlyArray2<f32>, sampling_frequency: f64, channel_depths: PyReadonlyArray1<f32>,
+            num_random_chunks: usize, chunk_duration_s: f64, seed: Option<u64>, psd_hf_threshold: f64, dead_channel_threshold: f64,
+            noisy_channel_threshold: f64, outside_channel_threshold: f64, n_neighbors: usize, nyquist_threshold: f64,
+            welch_window_ms: f64) -> (Vec<&'static str>, Bound<'py,PyArray1<bool>>) {
+    assert!(n_neighbors % 2 == 1, "n_neighbors must be odd");
+
+    let traces: ArrayView2<f32> = traces.as_array();
+    let channel_depths: ArrayView1<f32> = channel_depths.as_array();
+    assert_eq!(channel_depths.len(), traces.ncols(), "channel_depths must have one entry per channel");
+
+    let params = BadChannelParams {
+        sampling_frequency, psd_hf_threshold, dead_channel_threshold, noisy_channel_threshold, outside_channel_threshold,
+        n_neighbors, nyquist_threshold, welch_window_size: (welch_window_ms * sampling_frequency / 1000.0) as usize,
+    };
+    let chunk_size = ((chunk_duration_s * sampling_frequency) as usize).min(traces.nrows());
+
+    let labels: Vec<usize> = py.detach(|| {
+        let starts = random_chunk_starts(traces.nrows(), num_random_chunks, chunk_size, seed);
+        let chunks: Vec<ArrayView2<f32>> = starts.iter().map(|&start| traces.slice(s![start..start + chunk_size, ..])).collect();
+        detect_bad_channels(&chunks, &channel_depths, &params)
+    });
+
+    let channel_mask: Array1<bool> = labels.iter().map(|&label| label == GOOD).collect();
+    (labels.iter().map(|&label| CHANNEL_LABELS[label]).collect(), channel_mask.into_pyarray(py))
+}
+
+// Labels every chunk independently, then keeps the most frequent label of each channel (the lowest on ties).
+pub(crate) fn detect_bad_channels(chunks: &[ArrayView2<f32>], channel_depths: &ArrayView1<f32>, params: &BadChannelParams) -> Vec<usize> {
+    let n_channels = channel_depths.len();
+
+    // the features are computed along the probe, from the deepest to the most superficial channel
+    let mut depth_order: Vec<usize> = (0..n_channels).collect();
+    depth_order.sort_by(|&a, &b| channel_depths[a].total_cmp(&channel_depths[b]));
+
+    let chunk_labels: Vec<Vec<usize>> = chunks.par_iter()
+        .map(|chunk| {
+            let sorted: Array2<f32> = chunk.select(Axis(1), &depth_order);
+            detect_bad_channels_on_chunk(&sorted.view(), params)
+        })
+        .collect();
+
+    let mut labels: Vec<usize> = vec![GOOD; n_channels];
+    for (sorted_ind, &ch) in depth_order.iter().enumerate() {
+        let mut counts = [0usize; 4];
+        for chunk in &chunk_labels {
+            counts[chunk[sorted_ind]] += 1;
+        }
+        labels[ch] = (0..counts.len()).rev().max_by_key(|&label| counts[label]).unwrap_or(GOOD);
+    }
+    labels
+}
+
+// IBL coherence + PSD criteria on one chunk whose channels are sorted by depth: a channel poorly correlated with the
+// median signal compared to its neighbours is dead, an over-correlated one or one with strong high-frequency power
+// is noisy, and the contiguous run of weakly correlated channels at the top of the probe is outside the brain.
+fn detect_bad_channels_on_chunk(chunk: &ArrayView2<f32>, params: &BadChannelParams) -> Vec<usize> {
+    let n_channels = chunk.ncols();
+
+    let mut raw: Array2<f64> = chunk.mapv(|value| value as f64);
+    let mean = raw.mean_axis(Axis(0)).unwrap_or_else(|| Array1::zeros(n_channels));
+    raw -= &mean;
+
+    let reference: Array1<f64> = raw.outer_iter()
+        .map(|row| {
+            let mut values: Vec<f32> = row.iter().map(|&value| value as f32).collect();
+            median(&mut values) as f64
+        })
+        .collect();
+    let reference_power = reference.dot(&reference);
+    let xcorr: Vec<f64> = raw.axis_iter(Axis(1))
+        .map(|trace| if reference_power > 0.0 { trace.dot(&reference) / reference_power } else { 0.0 })
+        .collect();
+
+    let trend = median_filter(&xcorr, params.n_neighbors);
+    let psd_hf = high_frequency_power(&raw.view(), params);
+
+    let mut labels: Vec<usize> = vec![GOOD; n_channels];
+
+    // only the run of low-coherence channels reaching the top of the probe is outside of the brain; as in IBL, the
+    // dead and noisy labels are applied afterwards and take precedence
+    if n_channels > 0 && trend[n_channels - 1] - 1.0 < params.outside_channel_threshold {
+        for ch in (0..n_channels).rev() {
+            if trend[ch] - 1.0 >= params.outside_channel_threshold {
+                break;
+            }
+            labels[ch] = OUT;
+        }
+    }
+
+    for ch in 0..n_channels {
+        let xcorr_neighbors = xcorr[ch] - trend[ch];
+        if xcorr_neighbors < params.dead_channel_threshold {
+            labels[ch] = DEAD;
+        }
+        if psd_hf[ch] > params.psd_hf_threshold || xcorr_neighbors > params.noisy_channel_threshold {
+            labels[ch] = NOISE;
+        }
+    }
+
+    labels
+}
+
+// Running median over n_neighbors channels, the edges being padded with the first and last values.
+fn median_filter(values: &[f64], n_neighbors: usize) -> Vec<f64> {
+    let n = values.len();
+    let half = n_neighbors / 2;
+    let mut window: Vec<f32> = Vec::with_capacity(n_neighbors);
+
+    (0..n)
+        .map(|i| {
+            window.clear();
+            for k in 0..n_neighbors {
+                let j = (i + k).saturating_sub(half).min(n - 1);
+                window.push(values[j] as f32);
+            }
+            median(&mut window) as f64
+        })
+        .collect()
+}
+
+// Welch power spectral density (Hann window, 50% overlap, constant detrend, one-sided density) averaged over the
+// frequencies above nyquist_threshold times the Nyquist frequency.
+fn high_frequency_power(raw: &ArrayView2<f64>, params: &BadChannelParams) -> Vec<f64> {
+    let n_samples = raw.nrows();
+    let segment_size = params.welch_window_size.clamp(1, n_samples.max(1));
+    let step = (segment_size / 2).max(1);
+    let n_frequencies = segment_size / 2 + 1;
+
+    let window: Vec<f64> = (0..segment_size)
+        .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f64 / segment_size as f64).cos())
+        .collect();
+    let window_power: f64 = window.iter().map(|w| w * w).sum();
+    let min_frequency = params.sampling_frequency / 2.0 * params.nyquist_threshold;
+    let high_bins: Vec<usize> = (0..n_frequencies)
+        .filter(|&k| k as f64 * params.sampling_frequency / segment_size as f64 > min_frequency)
+        .collect();
+
+    let mut planner = RealFftPlanner::<f64>::new();
+    let fft = planner.plan_fft_forward(segment_size);
+
+    raw.axis_iter(Axis(1))
+        .map(|trace| {
+            let mut segment = fft.make_input_vec();
+            let mut spectrum = fft.make_output_vec();
+            let mut psd: Vec<f64> = vec![0.0; n_frequencies];
+            let mut n_segments = 0;
+
+            let mut start = 0;
+            while start + segment_size <= n_samples {
+                let values = trace.slice(s![start..start + segment_size]);
+                let mean = values.mean().unwrap_or(0.0);
+                for ((value, &sample), &w) in segment.iter_mut().zip(values.iter()).zip(window.iter()) {
+                    *value = (sample - mean) * w;
+                }
+                fft.process(&mut segment, &mut spectrum).unwrap();
+                for (power, bin) in psd.iter_mut().zip(spectrum.iter()) {
+                    *power += bin.norm_sqr();
+                }
+                n_segments += 1;
+                start += step;
+            }
+
+            if n_segments == 0 || high_bins.is_empty() {
+                return 0.0;
+            }
+            let scale = 1.0 / (params.sampling_frequency * window_power * n_segments as f64);
+            let total: f64 = high_bins.iter()
+                .map(|&k| {
+                    // every bin but DC and Nyquist holds the power of the negative frequencies too
+                    let one_sided = if k == 0 || (segment_size.is_multiple_of(2) && k == n_frequencies - 1) { 1.0 } else { 2.0 };
+                    psd[k] * scale * one_sided
+                })
+                .sum();
+            total / high_bins.len() as f64
+        })
+        .collect()
+}
@@ -0,0 +1,195 @@
+use std::f64::consts::PI;
+
+use ndarray::{s, Array2, ArrayView2, ArrayViewMut1, ArrayViewMut2, Axis};
+use ndarray::parallel::prelude::*;
+use numpy::{Complex64, IntoPyArray, PyArray2, PyReadonlyArray2, PyReadwriteArray2};
+use pyo3::prelude::*;
+
+// Second-order sections in the scipy layout: one row per section, [b0, b1, b2, a0, a1, a2] with a0 == 1.
+pub(crate) type Sos = Array2<f64>;
+
+#[pyfunction]
+pub fn butter_bandpass_sos_rust<'py>(py: Python<'py>, order: usize, freq_min: f64, freq_max: f64, sampling_frequency: f64) -> Bound<'py,PyArray2<f64>> {
+    butter_bandpass_sos(order, freq_min, freq_max, sampling_frequency).into_pyarray(py)
+}
+
+#[pyfunction]
+#[pyo3(signature = (traces, sos, margin, direction="forward-backward"))]
+pub fn bandpass_filter_rust_on_chunk<'py>(py: Python<'py>, traces: PyReadonlyArray2<f32>, sos: PyReadonlyArray2<f64>, margin: usize,
+            direction: &str) -> Bound<'py,PyArray2<f32>> {
+    assert!(["forward", "forward-backward"].contains(&direction), "direction must be 'forward' or 'forward-backward'");
+    assert_eq!(sos.as_array().ncols(), 6, "sos must have shape (n_sections, 6)");
+
+    let traces: ArrayView2<f32> = traces.as_array();
+    let sos: Sos = sos.as_array().to_owned();
+    assert!(traces.nrows() >= 2 * margin, "the chunk is shorter than its margins");
+
+    let filtered: Array2<f32> = py.detach(|| {
+        let mut filtered = traces.to_owned();
+        filter_traces_in_place(&mut filtered.view_mut(), &sos, direction == "forward-backward");
+        let n_samples = filtered.nrows();
+        filtered.slice(s![margin..n_samples - margin, ..]).to_owned()
+    });
+
+    filtered.into_pyarray(py)
+}
+
+#[pyfunction]
+#[pyo3(signature = (traces, sos, direction="forward-backward"))]
+pub fn bandpass_filter_rust_in_place(py: Python<'_>, mut traces: PyReadwriteArray2<f32>, sos: PyReadonlyArray2<f64>, direction: &str) {
+    assert!(["forward", "forward-backward"].contains(&direction), "direction must be 'forward' or 'forward-backward'");
+    assert_eq!(sos.as_array().ncols(), 6, "sos must have shape (n_sections, 6)");
+
+    let mut traces: ArrayViewMut2<f32> = traces.as_array_mut();
+    let sos: Sos = sos.as_array().to_owned();
+
+    py.detach(|| {filter_traces_in_place(&mut traces, &sos, direction == "forward-backward")});
+}
+
+// Filters every channel (column) in place, in parallel over channels. The caller is responsible for the chunk margins:
+// the first and last samples carry the filter transient and should be discarded.
+pub(crate) fn filter_traces_in_place(traces: &mut ArrayViewMut2<f32>, sos: &Sos, zero_phase: bool) {
+    if traces.nrows() == 0 {
+        return;
+    }
+    let zi: Array2<f64> = sosfilt_zi(sos);
+
+    traces.axis_iter_mut(Axis(1)).into_par_iter().for_each(|mut trace| {
+        sosfilt(&mut trace, sos, &zi, false);
+        if zero_phase {
+            sosfilt(&mut trace, sos, &zi, true);
+        }
+    });
+}
+
+// Direct form II transposed, one section after the other, with the state initialised to the steady state
+// of a constant input equal to the first sample (as scipy's sosfilt_zi does).
+fn sosfilt(trace: &mut ArrayViewMut1<f32>, sos: &Sos, zi: &Array2<f64>, reverse: bool) {
+    let n_samples = trace.len();
+    let first = if reverse { trace[n_samples - 1] } else { trace[0] } as f64;
+
+    for (section, section_zi) in sos.outer_iter().zip(zi.outer_iter()) {
+        let (b0, b1, b2, a1, a2) = (section[0], section[1], section[2], section[4], section[5]);
+        let mut z1 = section_zi[0] * first;
+        let mut z2 = section_zi[1] * first;
+
+        for k in 0..n_samples {
+            let i = if reverse { n_samples - 1 - k } else { k };
+            let x = trace[i] as f64;
+            let y = b0 * x + z1;
+            z1 = b1 * x - a1 * y + z2;
+            z2 = b2 * x - a2 * y;
+            trace[i] = y as f32;
+        }
+    }
+}
+
+fn sosfilt_zi(sos: &Sos) -> Array2<f64> {
+    let mut zi: Array2<f64> = Array2::zeros((sos.nrows(), 2));
+    let mut scale = 1.0;
+
+    for (i, section) in sos.outer_iter().enumerate() {
+        let (b0, b1, b2, a1, a2) = (section[0], section[1], section[2], section[4], section[5]);
+        // steady state of the section for a constant unit input
+        let section_gain = (b0 + b1 + b2) / (1.0 + a1 + a2);
+        zi[[i, 0]] = scale * (b1 + b2 - (a1 + a2) * section_gain);
+        zi[[i, 1]] = scale * (b2 - a2 * section_gain);
+
+        scale *= section_gain;
+    }
+
+    zi
+}
+
+// Digital Butterworth bandpass of the given order (the resulting filter has order 2 * order), designed through the
+// analog prototype, the lowpass-to-bandpass transform and the bilinear transform, like scipy.signal.butter.
+pub(crate) fn butter_bandpass_sos(order: usize, freq_min: f64, freq_max: f64, sampling_frequency: f64) -> Sos {
+    assert!(order > 0, "order must be positive");
+    assert!(0.0 < freq_min && freq_min < freq_max && freq_max < sampling_frequency / 2.0,
+        "frequencies must satisfy 0 < freq_min < freq_max < sampling_frequency / 2");
+
+    // pre-warped analog band edges
+    let fs2 = 2.0 * sampling_frequency;
+    let low = fs2 * (PI * freq_min / sampling_frequency).tan();
+    let high = fs2 * (PI * freq_max / sampling_frequency).tan();
+    let bandwidth = high - low;
+    let center_sq = low * high;
+
+    let fs2_c = Complex64::new(fs2, 0.0);
+    let mut digital_poles: Vec<Complex64> = Vec::with_capacity(2 * order);
+    let mut gain = bandwidth.powi(order as i32);
+    let mut denominator = Complex64::new(1.0, 0.0);
+    for k in 0..order {
+        // analog prototype pole on the left half of the unit circle
+        let theta = PI * (2 * k + order + 1) as f64 / (2 * order) as f64;
+        let scaled = Complex64::from_polar(bandwidth / 2.0, theta);
+        let root = (scaled * scaled - center_sq).sqrt();
+
+        for analog in [scaled + root, scaled - root] {
+            digital_poles.push((fs2_c + analog) / (fs2_c - analog));
+            denominator *= fs2_c - analog;
+        }
+    }
+    // the order zeros at s=0 map to z=1, the order zeros at infinity map to z=-1
+    gain *= fs2.powi(order as i32) / denominator.re;
+
+    // pair conjugate poles together; the few real poles are paired with each other
+    let mut complex_poles: Vec<Complex64> = digital_poles.iter().copied().filter(|p| p.im > 1e-12).collect();
+    let mut real_poles: Vec<f64> = digital_poles.iter().filter(|p| p.im.abs() <= 1e-12).map(|p| p.re).collect();
+    // poles closest to the unit circle go last, as scipy does
+    complex_poles.sort_by(|a, b| a.norm().total_cmp(&b.norm()));
+    real_poles.sort_by(|a, b| a.abs().total_cmp(&b.abs()));
+
+    let mut sections: Vec<[f64; 6]> = Vec::with_capacity(order);
+    for pair in real_poles.chunks(2) {
+        let (p1, p2) = (pair[0], *pair.get(1).unwrap_or(&0.0));
+        sections.push([1.0, 0.0, -1.0, 1.0, -(p1 + p2), p1 * p2]);
+    }
+    for p in complex_poles {
+        sections.push([1.0, 0.0, -1.0, 1.0, -2.0 * p.re, p.norm_sqr()]);
+    }
+
+    let mut sos: Sos = Array2::zeros((sections.len(), 6));
+    for (i, section) in sections.iter().enumerate() {
+        for (j, &coefficient) in section.iter().enumerate() {
+            sos[[i, j]] = coefficient;
+        }
+    }
+    for j in 0..3 {
+        sos[[0, j]] *= gain;
+    }
+
+    sos
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gain(sos: &Sos, frequency: f64, sampling_frequency: f64) -> f64 {
+        let z_inv = Complex64::from_polar(1.0, -2.0 * PI * frequency / sampling_frequency);
+        sos.outer_iter()
+            .map(|section| {
+                let numerator = section[0] + section[1] * z_inv + section[2] * z_inv * z_inv;
+                let denominator = section[3] + section[4] * z_inv + section[5] * z_inv * z_inv;
+                numerator / denominator
+            })
+            .product::<Complex64>()
+            .norm()
+    }
+
+    #[test]
+    fn butter_bandpass_sos_is_3db_down_at_the_band_edges() {
+        let sampling_frequency = 30000.0;
+        let (freq_min, freq_max) = (300.0, 6000.0);
+        for order in [1, 2, 5] {
+            let sos = butter_bandpass_sos(order, freq_min, freq_max, sampling_frequency);
+            for frequency in [freq_min, freq_max] {
+                let edge_gain = gain(&sos, frequency, sampling_frequency);
+                assert!((edge_gain - 0.5f64.sqrt()).abs() < 1e-6, "order {order}: gain {edge_gain} at {frequency} Hz");
+            }
+            let center_gain = gain(&sos, (freq_min * freq_max).sqrt(), sampling_frequency);
+            assert!((center_gain - 1.0).abs() < 1e-2, "order {order}: gain {center_gain} in the pass band");
+        }
+    }
+}
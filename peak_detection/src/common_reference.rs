@@ -0,0 +1,118 @@
+use ndarray::{Array2, ArrayView2, ArrayViewMut2, Axis};
+use ndarray::parallel::prelude::*;
+use numpy::{IntoPyArray, PyArray2, PyReadonlyArray2};
+use pyo3::prelude::*;
+
+use crate::rust_peak_detection_locally_exclusive_sliding_window::neighbours_mask_to_adjency_list;
+
+// Channels referenced together: either disjoint groups sharing one reference (global, per shank or per group),
+// or, for a local reference, the list of channels whose values are combined to reference each channel, the channel
+// itself excluded.
+pub(crate) enum ReferenceChannels {
+    Global(Vec<Vec<usize>>),
+    Local(Vec<Vec<usize>>),
+}
+
+#[pyfunction]
+#[pyo3(signature = (traces, reference="global", operator="median", groups=None, neighbours_mask=None))]
+pub fn common_reference_rust_on_chunk<'py>(py: Python<'py>, traces: PyReadonlyArray2<f32>, reference: &str, operator: &str,
+            groups: Option<Vec<usize>>, neighbours_mask: Option<PyReadonlyArray2<bool>>) -> Bound<'py,PyArray2<f32>> {
+    assert!(["median", "average"].contains(&operator), "operator must be 'median' or 'average'");
+
+    let traces: ArrayView2<f32> = traces.as_array();
+    let n_channels = traces.ncols();
+
+    let reference_channels = reference_channels(reference, n_channels, groups, neighbours_mask.as_ref().map(|mask| mask.as_array()));
+
+    let referenced: Array2<f32> = py.detach(|| {
+        let mut referenced = traces.to_owned();
+        common_reference_in_place(&mut referenced.view_mut(), &reference_channels, operator);
+        referenced
+    });
+
+    referenced.into_pyarray(py)
+}
+
+pub(crate) fn reference_channels(reference: &str, n_channels: usize, groups: Option<Vec<usize>>, neighbours_mask: Option<ArrayView2<bool>>) -> ReferenceChannels {
+    assert!(["global", "local"].contains(&reference), "reference must be 'global' or 'local'");
+
+    if reference == "global" {
+        let groups: Vec<usize> = groups.unwrap_or_else(|| vec![0; n_channels]);
+        assert_eq!(groups.len(), n_channels, "groups must give one group per channel");
+        ReferenceChannels::Global(channel_groups(&groups))
+    } else {
+        let neighbours_mask: ArrayView2<bool> = neighbours_mask.expect("a local reference needs neighbours_mask");
+        // a channel never references itself, otherwise its own spikes are subtracted from it
+        let adjency_list: Vec<Vec<usize>> = neighbours_mask_to_adjency_list(&neighbours_mask).into_iter().enumerate()
+            .map(|(ch, neighbours)| neighbours.into_iter().filter(|&neighbour| neighbour != ch).collect())
+            .collect();
+        ReferenceChannels::Local(adjency_list)
+    }
+}
+
+pub(crate) fn channel_groups(groups: &[usize]) -> Vec<Vec<usize>> {
+    let n_groups = groups.iter().max().map_or(0, |&group| group + 1);
+    let mut channels: Vec<Vec<usize>> = vec![Vec::new(); n_groups];
+    for (ch, &group) in groups.iter().enumerate() {
+        channels[group].push(ch);
+    }
+    channels.retain(|group| !group.is_empty());
+    channels
+}
+
+// Subtracts the reference sample by sample, in parallel over samples.
+pub(crate) fn common_reference_in_place(traces: &mut ArrayViewMut2<f32>, reference_channels: &ReferenceChannels, operator: &str) {
+    let use_median = operator == "median";
+    let n_channels = traces.ncols();
+
+    traces.axis_iter_mut(Axis(0)).into_par_iter().for_each_init(
+        || (Vec::with_capacity(n_channels), Vec::with_capacity(n_channels)),
+        |(buffer, references), mut row| {
+            references.clear();
+            match reference_channels {
+                ReferenceChannels::Global(groups) => {
+                    for group in groups {
+                        buffer.clear();
+                        buffer.extend(group.iter().map(|&ch| row[ch]));
+                        let value = reduce(buffer, use_median);
+                        references.extend(group.iter().map(|&ch| (ch, value)));
+                    }
+                }
+                ReferenceChannels::Local(adjency_list) => {
+                    for (ch, neighbours) in adjency_list.iter().enumerate() {
+                        if neighbours.is_empty() {
+                            continue;
+                        }
+                        buffer.clear();
+                        buffer.extend(neighbours.iter().map(|&neighbour| row[neighbour]));
+                        references.push((ch, reduce(buffer, use_median)));
+                    }
+                }
+            }
+
+            for &(ch, value) in references.iter() {
+                row[ch] -= value;
+            }
+        }
+    );
+}
+
+fn reduce(values: &mut [f32], use_median: bool) -> f32 {
+    if use_median {
+        median(values)
+    } else {
+        values.iter().sum::<f32>() / values.len() as f32
+    }
+}
+
+// Median with the numpy convention for an even number of values (mean of the two central values).
+pub(crate) fn median(values: &mut [f32]) -> f32 {
+    let n = values.len();
+    let (lower, &mut upper, _) = values.select_nth_unstable_by(n / 2, f32::total_cmp);
+    if n % 2 == 1 {
+        upper
+    } else {
+        let lower_max = lower.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        (lower_max + upper) / 2.0
+    }
+}
@@ -0,0 +1,184 @@
+use ndarray::{Array1, ArrayView1, ArrayView2};
+use ndarray::parallel::prelude::*;
+use numpy::{IntoPyArray, PyReadonlyArray1, PyReadonlyArray2};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+pub(crate) struct Peaks<'a> {
+    pub sample_indices: ArrayView1<'a, usize>,
+    pub channel_indices: ArrayView1<'a, usize>,
+}
+
+pub(crate) struct Comparison {
+    pub gt_matched: Array1<bool>,
+    pub detected_matched: Array1<bool>,
+}
+
+// Matches detected peaks to ground-truth spikes one-to-one: a pair is eligible when the peaks are at most delta_samples
+// apart and their channels at most max_distance_um apart, and eligible pairs are matched from the closest in time (then
+// in space) onwards. Unmatched ground-truth spikes are misses and unmatched detections false positives. Hits and misses
+// are reported per unit and per bin of absolute ground-truth amplitude (num_amplitude_bins bins over the amplitude
+// range when amplitude_bins edges are not given), which allows regression-testing every detection variant.
+// A false positive belongs to no unit, so false positives are reported per detected channel instead, and per bin of
+// absolute detected amplitude with the same edges when detected_amplitudes is given (None otherwise).
+#[pyfunction]
+#[pyo3(signature = (detected_sample_indices, detected_channel_indices, gt_sample_indices, gt_channel_indices, gt_unit_indices, gt_amplitudes,
+    num_units, channel_locations, delta_samples=10, max_distance_um=50.0, amplitude_bins=None, num_amplitude_bins=10, detected_amplitudes=None))]
+#[allow(clippy::too_many_arguments)]
+pub fn compare_peaks_to_ground_truth_rust<'py>(py: Python<'py>, detected_sample_indices: PyReadonlyArray1<usize>,
+            detected_channel_indices: PyReadonlyArray1<usize>, gt_sample_indices: PyReadonlyArray1<usize>, gt_channel_indices: PyReadonlyArray1<usize>,
+            gt_unit_indices: PyReadonlyArray1<usize>, gt_amplitudes: PyReadonlyArray1<f32>, num_units: usize, channel_locations: PyReadonlyArray2<f32>,
+            delta_samples: usize, max_distance_um: f32, amplitude_bins: Option<Vec<f32>>, num_amplitude_bins: usize,
+            detected_amplitudes: Option<PyReadonlyArray1<f32>>) -> PyResult<Bound<'py,PyDict>> {
+    let detected = Peaks { sample_indices: detected_sample_indices.as_array(), channel_indices: detected_channel_indices.as_array() };
+    let ground_truth = Peaks { sample_indices: gt_sample_indices.as_array(), channel_indices: gt_channel_indices.as_array() };
+    let gt_unit_indices: ArrayView1<usize> = gt_unit_indices.as_array();
+    let gt_amplitudes: ArrayView1<f32> = gt_amplitudes.as_array();
+    let channel_locations: ArrayView2<f32> = channel_locations.as_array();
+    assert_eq!(detected.sample_indices.len(), detected.channel_indices.len(), "detected sample and channel indices must have the same length");
+    let detected_amplitudes: Option<ArrayView1<f32>> = detected_amplitudes.as_ref().map(|amplitudes| amplitudes.as_array());
+    assert!(detected_amplitudes.is_none_or(|amplitudes| amplitudes.len() == detected.sample_indices.len()),
+        "detected_amplitudes must have one entry per detected peak");
+    assert!(ground_truth.sample_indices.len() == ground_truth.channel_indices.len() && ground_truth.channel_indices.len() == gt_unit_indices.len()
+        && gt_unit_indices.len() == gt_amplitudes.len(), "ground-truth arrays must have the same length");
+
+    let amplitude_bins: Vec<f32> = amplitude_bins.unwrap_or_else(|| linear_amplitude_bins(&gt_amplitudes, num_amplitude_bins));
+    assert!(amplitude_bins.len() >= 2, "amplitude_bins needs at least two edges");
+
+    let comparison = py.detach(|| compare_peaks(&detected, &ground_truth, &channel_locations, delta_samples, max_distance_um));
+
+    let mut hits_per_unit: Array1<usize> = Array1::zeros(num_units);
+    let mut misses_per_unit: Array1<usize> = Array1::zeros(num_units);
+    let mut hits_per_amplitude_bin: Array1<usize> = Array1::zeros(amplitude_bins.len() - 1);
+    let mut misses_per_amplitude_bin: Array1<usize> = Array1::zeros(amplitude_bins.len() - 1);
+    for ((&matched, &unit), &amplitude) in comparison.gt_matched.iter().zip(gt_unit_indices.iter()).zip(gt_amplitudes.iter()) {
+        let (per_unit, per_bin) = if matched { (&mut hits_per_unit, &mut hits_per_amplitude_bin) } else { (&mut misses_per_unit, &mut misses_per_amplitude_bin) };
+        per_unit[unit] += 1;
+        if let Some(bin) = amplitude_bin(amplitude.abs(), &amplitude_bins) {
+            per_bin[bin] += 1;
+        }
+    }
+    let num_false_positives = comparison.detected_matched.iter().filter(|&&matched| !matched).count();
+
+    let mut false_positives_per_channel: Array1<usize> = Array1::zeros(channel_locations.nrows());
+    for (&matched, &channel_ind) in comparison.detected_matched.iter().zip(detected.channel_indices.iter()) {
+        if !matched {
+            false_positives_per_channel[channel_ind] += 1;
+        }
+    }
+    let false_positives_per_amplitude_bin: Option<Array1<usize>> = detected_amplitudes.map(|detected_amplitudes| {
+        let mut per_bin: Array1<usize> = Array1::zeros(amplitude_bins.len() - 1);
+        for (&matched, &amplitude) in comparison.detected_matched.iter().zip(detected_amplitudes.iter()) {
+            if !matched && let Some(bin) = amplitude_bin(amplitude.abs(), &amplitude_bins) {
+                per_bin[bin] += 1;
+            }
+        }
+        per_bin
+    });
+
+    let results = PyDict::new(py);
+    results.set_item("gt_matched", comparison.gt_matched.into_pyarray(py))?;
+    results.set_item("detected_matched", comparison.detected_matched.into_pyarray(py))?;
+    results.set_item("hits_per_unit", hits_per_unit.into_pyarray(py))?;
+    results.set_item("misses_per_unit", misses_per_unit.into_pyarray(py))?;
+    results.set_item("amplitude_bins", Array1::from(amplitude_bins).into_pyarray(py))?;
+    results.set_item("hits_per_amplitude_bin", hits_per_amplitude_bin.into_pyarray(py))?;
+    results.set_item("misses_per_amplitude_bin", misses_per_amplitude_bin.into_pyarray(py))?;
+    results.set_item("num_false_positives", num_false_positives)?;
+    results.set_item("false_positives_per_channel", false_positives_per_channel.into_pyarray(py))?;
+    results.set_item("false_positives_per_amplitude_bin", false_positives_per_amplitude_bin.map(|per_bin| per_bin.into_pyarray(py)))?;
+    Ok(results)
+}
+
+pub(crate) fn compare_peaks(detected: &Peaks, ground_truth: &Peaks, channel_locations: &ArrayView2<f32>, delta_samples: usize,
+    max_distance_um: f32) -> Comparison {
+    let mut detected_order: Vec<usize> = (0..detected.sample_indices.len()).collect();
+    detected_order.sort_by_key(|&j| detected.sample_indices[j]);
+    let sorted_samples: Vec<usize> = detected_order.iter().map(|&j| detected.sample_indices[j]).collect();
+
+    let distance = |a: usize, b: usize| -> f32 {
+        channel_locations.row(a).iter().zip(channel_locations.row(b).iter()).map(|(x, y)| (x - y) * (x - y)).sum::<f32>().sqrt()
+    };
+
+    // eligible (delta, distance, gt index, detected index) pairs, found in parallel over ground-truth spikes
+    let mut pairs: Vec<(usize, f32, usize, usize)> = (0..ground_truth.sample_indices.len()).into_par_iter()
+        .flat_map_iter(|i| {
+            let sample_ind = ground_truth.sample_indices[i];
+            let first = sorted_samples.partition_point(|&sample| sample + delta_samples < sample_ind);
+            let last = sorted_samples.partition_point(|&sample| sample <= sample_ind + delta_samples);
+            detected_order[first..last].iter()
+                .map(|&j| (sample_ind.abs_diff(detected.sample_indices[j]), distance(ground_truth.channel_indices[i], detected.channel_indices[j]), i, j))
+                .filter(|pair| pair.1 <= max_distance_um)
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    pairs.sort_by(|a, b| a.0.cmp(&b.0).then(a.1.total_cmp(&b.1)).then(a.2.cmp(&b.2)).then(a.3.cmp(&b.3)));
+
+    let mut gt_matched: Array1<bool> = Array1::from_elem(ground_truth.sample_indices.len(), false);
+    let mut detected_matched: Array1<bool> = Array1::from_elem(detected.sample_indices.len(), false);
+    for (_, _, i, j) in pairs {
+        if !gt_matched[i] && !detected_matched[j] {
+            gt_matched[i] = true;
+            detected_matched[j] = true;
+        }
+    }
+
+    Comparison { gt_matched, detected_matched }
+}
+
+fn linear_amplitude_bins(amplitudes: &ArrayView1<f32>, num_bins: usize) -> Vec<f32> {
+    let low = amplitudes.iter().map(|amplitude| amplitude.abs()).fold(f32::INFINITY, f32::min);
+    let high = amplitudes.iter().map(|amplitude| amplitude.abs()).fold(f32::NEG_INFINITY, f32::max);
+    if amplitudes.is_empty() || num_bins == 0 {
+        return vec![0.0, 0.0];
+    }
+    (0..=num_bins).map(|k| low + (high - low) * k as f32 / num_bins as f32).collect()
+}
+
+// Bins are [edge, next edge), the last one including its right edge like numpy.histogram.
+fn amplitude_bin(amplitude: f32, edges: &[f32]) -> Option<usize> {
+    let n_bins = edges.len() - 1;
+    if amplitude < edges[0] || amplitude > edges[n_bins] {
+        return None;
+    }
+    Some((edges.partition_point(|&edge| edge <= amplitude) - 1).min(n_bins - 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::noise_levels::mad_noise_levels;
+    use crate::peak_localization::radius_adjency_list;
+    use crate::rust_peak_detection_locally_exclusive_sliding_window::detect_peaks_locally_exclusive;
+    use crate::synthetic_recording::{generate_synthetic_recording, grid_channel_locations, SyntheticParams};
+
+    #[test]
+    fn sliding_window_detection_finds_the_synthetic_spikes() {
+        let channel_locations = grid_channel_locations(32, 2, 20.0);
+        let params = SyntheticParams {
+            duration_s: 2.0, sampling_frequency: 30000.0, num_units: 8, firing_rate_range: (5.0, 15.0), refractory_period_ms: 4.0,
+            amplitude_range: (80.0, 200.0), amplitude_jitter: 0.1, ms_before: 1.0, ms_after: 2.0, noise_level: 5.0, noise_spatial_decay_um: 30.0,
+            noise_temporal_correlation: 0.5, drift_amplitude_um: 0.0, drift_period_s: 30.0, seed: Some(0),
+        };
+        let recording = generate_synthetic_recording(&channel_locations.view(), &params);
+
+        // 5 MAD thresholds, and a neighbourhood wide enough for a spike not to be detected again on farther channels
+        let abs_thresholds = mad_noise_levels(&recording.traces.view()) * 5.0;
+        let adjency_list: Vec<Vec<usize>> = radius_adjency_list(&channel_locations.view(), 75.0);
+        let (sample_indices, channel_indices) = detect_peaks_locally_exclusive(&recording.traces.view(), "neg", &abs_thresholds.view(), 10,
+            &adjency_list, false);
+
+        let detected = Peaks { sample_indices: ArrayView1::from(&sample_indices), channel_indices: ArrayView1::from(&channel_indices) };
+        let ground_truth = Peaks {
+            sample_indices: ArrayView1::from(&recording.spike_sample_indices),
+            channel_indices: ArrayView1::from(&recording.spike_channel_indices),
+        };
+        let comparison = compare_peaks(&detected, &ground_truth, &channel_locations.view(), 10, 50.0);
+
+        let num_hits = comparison.gt_matched.iter().filter(|&&matched| matched).count();
+        let num_false_positives = comparison.detected_matched.iter().filter(|&&matched| !matched).count();
+        let hit_rate = num_hits as f32 / ground_truth.sample_indices.len() as f32;
+        assert!(hit_rate >= 0.95, "hit rate {hit_rate}");
+        assert!(num_false_positives <= 10, "{num_false_positives} false positives");
+    }
+}
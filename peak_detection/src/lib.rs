@@ -1,8 +1,47 @@
 use pyo3::prelude::*;
-// mod rust_peak_detection_locally_exclusive;
+// The mask, _sam and _sam2 variants are not registered in the Python module, so their pyfunctions are unused; they are
+// only compiled for the benches.
+#[allow(dead_code)]
+mod rust_peak_detection_locally_exclusive;
 mod rust_peak_detection_locally_exclusive_sliding_window;
-// mod rust_peak_detection_locally_exclusive_sam;
-// mod rust_peak_detection_locally_exclusive_sam2;
+#[allow(dead_code)]
+mod rust_peak_detection_locally_exclusive_sam;
+#[allow(dead_code)]
+mod rust_peak_detection_locally_exclusive_sam2;
+mod peak_waveform_validation;
+mod artifact_rejection;
+mod peak_interpolation;
+mod bandpass_filter;
+mod common_reference;
+mod noise_levels;
+mod whitening;
+mod phase_shift;
+mod bad_channels;
+mod peak_localization;
+mod waveform_extraction;
+mod svd_features;
+mod peak_clustering;
+mod template_estimation;
+mod template_matching;
+mod motion_estimation;
+mod motion_correction;
+mod quality_metrics;
+mod ground_truth_comparison;
+mod synthetic_recording;
+mod pipeline;
+
+// Detection kernels and synthetic data for the criterion benches (benches/), including the variants that are not
+// registered in the Python module.
+#[doc(hidden)]
+pub mod bench_support {
+    pub use crate::rust_peak_detection_locally_exclusive::detect_peaks_locally_exclusive as detect_peaks_mask;
+    pub use crate::rust_peak_detection_locally_exclusive_sliding_window::detect_peaks_locally_exclusive as detect_peaks_sliding_window;
+    pub use crate::rust_peak_detection_locally_exclusive_sliding_window::neighbours_mask_to_adjency_list;
+    pub use crate::rust_peak_detection_locally_exclusive_sam::detect_peaks_locally_exclusive as detect_peaks_sam;
+    pub use crate::rust_peak_detection_locally_exclusive_sam2::detect_peaks_locally_exclusive as detect_peaks_sam2;
+    pub use crate::noise_levels::mad_noise_levels;
+    pub use crate::synthetic_recording::{generate_synthetic_recording, grid_channel_locations, SyntheticParams};
+}
 
 #[pymodule]
 pub fn peak_detection(m: &Bound<'_, PyModule>) -> PyResult<()> {
@@ -10,5 +49,36 @@ pub fn peak_detection(m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add_function(wrap_pyfunction!(rust_peak_detection_locally_exclusive_sliding_window::detect_peaks_rust_locally_exclusive_on_chunk, m)?)?;
     // m.add_function(wrap_pyfunction!(rust_peak_detection_locally_exclusive_sam::detect_peaks_rust_locally_exclusive_on_chunk, m)?)?;
     // m.add_function(wrap_pyfunction!(rust_peak_detection_locally_exclusive_sam2::detect_peaks_rust_locally_exclusive_on_chunk, m)?)?;
+    m.add_function(wrap_pyfunction!(peak_waveform_validation::validate_peak_waveforms_rust_on_chunk, m)?)?;
+    m.add_function(wrap_pyfunction!(artifact_rejection::detect_peaks_rust_locally_exclusive_artifact_rejection_on_chunk, m)?)?;
+    m.add_function(wrap_pyfunction!(peak_interpolation::interpolate_peak_times_rust_on_chunk, m)?)?;
+    m.add_function(wrap_pyfunction!(bandpass_filter::butter_bandpass_sos_rust, m)?)?;
+    m.add_function(wrap_pyfunction!(bandpass_filter::bandpass_filter_rust_on_chunk, m)?)?;
+    m.add_function(wrap_pyfunction!(bandpass_filter::bandpass_filter_rust_in_place, m)?)?;
+    m.add_function(wrap_pyfunction!(common_reference::common_reference_rust_on_chunk, m)?)?;
+    m.add_function(wrap_pyfunction!(noise_levels::get_noise_levels_rust, m)?)?;
+    m.add_function(wrap_pyfunction!(whitening::compute_whitening_matrix_rust, m)?)?;
+    m.add_function(wrap_pyfunction!(whitening::whiten_rust_on_chunk, m)?)?;
+    m.add_function(wrap_pyfunction!(whitening::detect_peaks_rust_locally_exclusive_whitened_on_chunk, m)?)?;
+    m.add_function(wrap_pyfunction!(phase_shift::neuropixels_sample_shifts_rust, m)?)?;
+    m.add_function(wrap_pyfunction!(phase_shift::phase_shift_rust_on_chunk, m)?)?;
+    m.add_function(wrap_pyfunction!(bad_channels::detect_bad_channels_rust, m)?)?;
+    m.add_function(wrap_pyfunction!(peak_localization::localize_peaks_center_of_mass_rust_on_chunk, m)?)?;
+    m.add_function(wrap_pyfunction!(peak_localization::localize_peaks_monopolar_triangulation_rust_on_chunk, m)?)?;
+    m.add_function(wrap_pyfunction!(waveform_extraction::extract_waveforms_rust_on_chunk, m)?)?;
+    m.add_function(wrap_pyfunction!(waveform_extraction::extract_waveforms_rust_into, m)?)?;
+    m.add_function(wrap_pyfunction!(svd_features::fit_temporal_svd_rust, m)?)?;
+    m.add_function(wrap_pyfunction!(svd_features::project_temporal_svd_rust, m)?)?;
+    m.add_function(wrap_pyfunction!(svd_features::extract_svd_features_rust_on_chunk, m)?)?;
+    m.add_function(wrap_pyfunction!(peak_clustering::cluster_peaks_density_peaks_rust, m)?)?;
+    m.add_function(wrap_pyfunction!(template_matching::template_matching_rust_on_chunk, m)?)?;
+    m.add_function(wrap_pyfunction!(motion_estimation::estimate_motion_decentralized_rust, m)?)?;
+    m.add_function(wrap_pyfunction!(motion_correction::interpolate_motion_rust_on_chunk, m)?)?;
+    m.add_function(wrap_pyfunction!(quality_metrics::compute_quality_metrics_rust, m)?)?;
+    m.add_function(wrap_pyfunction!(ground_truth_comparison::compare_peaks_to_ground_truth_rust, m)?)?;
+    m.add_function(wrap_pyfunction!(synthetic_recording::generate_synthetic_recording_rust, m)?)?;
+    m.add_class::<pipeline::PeakDetectionPipeline>()?;
+    m.add_class::<template_estimation::TemplateEstimator>()?;
+    m.add_class::<peak_localization::GridConvolutionLocalizer>()?;
     Ok(())
 }
\ No newline at end of file
@@ -0,0 +1,171 @@
+use nalgebra::DMatrix;
+use ndarray::{s, Array2, ArrayView1, ArrayView2};
+use numpy::{IntoPyArray, PyArray2, PyReadonlyArray1, PyReadonlyArray2};
+use pyo3::prelude::*;
+
+pub(crate) struct InterpolationParams {
+    pub method: String,
+    pub sigma_um: f32,
+    pub p: f32,
+    pub num_closest: usize,
+    pub force_zeros: bool,
+}
+
+// Resamples the chunk so that every channel reads the signal at its own location shifted by the estimated motion
+// (see estimate_motion_decentralized_rust), i.e. at depth y + displacement(t, y), the displacement being linearly
+// interpolated between the window centers. The interpolation kernel is computed once per temporal bin:
+// - "kriging": exp(-(d / sigma_um)^p) kernel between the shifted and the original positions, times the inverse of the
+//   (regularized) kernel between the original positions, like spikeinterface's interpolate_motion,
+// - "idw": inverse distance weighting of the num_closest channels,
+// - "nearest": the closest channel.
+// With border_mode="force_zeros", channels whose shifted position leaves the probe are zeroed instead of extrapolated.
+#[pyfunction]
+#[pyo3(signature = (traces, start_sample, sampling_frequency, channel_locations, displacement, temporal_bins, window_centers, method="kriging",
+    sigma_um=20.0, p=1.0, num_closest=3, border_mode="force_extrapolate"))]
+#[allow(clippy::too_many_arguments)]
+pub fn interpolate_motion_rust_on_chunk<'py>(py: Python<'py>, traces: PyReadonlyArray2<f32>, start_sample: usize, sampling_frequency: f64,
+            channel_locations: PyReadonlyArray2<f32>, displacement: PyReadonlyArray2<f32>, temporal_bins: PyReadonlyArray1<f64>,
+            window_centers: PyReadonlyArray1<f32>, method: &str, sigma_um: f32, p: f32, num_closest: usize, border_mode: &str) -> Bound<'py,PyArray2<f32>> {
+    let params = InterpolationParams::new(method, sigma_um, p, num_closest, border_mode);
+
+    let traces: ArrayView2<f32> = traces.as_array();
+    let channel_locations: ArrayView2<f32> = channel_locations.as_array();
+    let displacement: ArrayView2<f32> = displacement.as_array();
+    let temporal_bins: ArrayView1<f64> = temporal_bins.as_array();
+    let window_centers: ArrayView1<f32> = window_centers.as_array();
+    assert_eq!(channel_locations.nrows(), traces.ncols(), "channel_locations must have one row per channel");
+    assert_eq!(displacement.dim(), (temporal_bins.len(), window_centers.len()), "displacement must be (n_temporal_bins, n_windows)");
+    assert!(!temporal_bins.is_empty() && !window_centers.is_empty(), "the motion needs at least one temporal bin and one window");
+
+    let corrected: Array2<f32> = py.detach(|| {
+        interpolate_motion(&traces, start_sample, sampling_frequency, &channel_locations, &displacement, &temporal_bins, &window_centers, &params)
+    });
+
+    corrected.into_pyarray(py)
+}
+
+impl InterpolationParams {
+    pub(crate) fn new(method: &str, sigma_um: f32, p: f32, num_closest: usize, border_mode: &str) -> Self {
+        assert!(["kriging", "idw", "nearest"].contains(&method), "method must be 'kriging', 'idw' or 'nearest'");
+        assert!(["force_extrapolate", "force_zeros"].contains(&border_mode), "border_mode must be 'force_extrapolate' or 'force_zeros'");
+        InterpolationParams { method: method.to_string(), sigma_um, p, num_closest: num_closest.max(1), force_zeros: border_mode == "force_zeros" }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn interpolate_motion(traces: &ArrayView2<f32>, start_sample: usize, sampling_frequency: f64, channel_locations: &ArrayView2<f32>,
+    displacement: &ArrayView2<f32>, temporal_bins: &ArrayView1<f64>, window_centers: &ArrayView1<f32>, params: &InterpolationParams) -> Array2<f32> {
+    let n_samples = traces.nrows();
+    let mut corrected: Array2<f32> = Array2::zeros(traces.raw_dim());
+
+    // the inverse of the kernel between the original positions does not depend on the motion
+    let kriging_inverse: Option<DMatrix<f64>> = (params.method == "kriging").then(|| kriging_inverse(channel_locations, params));
+
+    // samples go to the temporal bin with the closest center
+    let bin_edges: Vec<f64> = temporal_bins.iter().zip(temporal_bins.iter().skip(1)).map(|(&left, &right)| (left + right) / 2.0).collect();
+    let bin_of = |sample_ind: usize| -> usize {
+        let time = (start_sample + sample_ind) as f64 / sampling_frequency;
+        bin_edges.partition_point(|&edge| edge <= time)
+    };
+
+    let mut start = 0;
+    while start < n_samples {
+        let bin = bin_of(start);
+        let mut end = start + 1;
+        while end < n_samples && bin_of(end) == bin {
+            end += 1;
+        }
+
+        let kernel = interpolation_kernel(channel_locations, &displacement.row(bin), window_centers, kriging_inverse.as_ref(), params);
+        corrected.slice_mut(s![start..end, ..]).assign(&traces.slice(s![start..end, ..]).dot(&kernel.t()));
+        start = end;
+    }
+
+    corrected
+}
+
+fn kriging_distance_kernel(distance: f32, params: &InterpolationParams) -> f64 {
+    (-((distance / params.sigma_um) as f64).powf(params.p as f64)).exp()
+}
+
+fn distance(a: &ArrayView1<f32>, b: &ArrayView1<f32>) -> f32 {
+    a.iter().zip(b.iter()).map(|(x, y)| (x - y) * (x - y)).sum::<f32>().sqrt()
+}
+
+fn kriging_inverse(channel_locations: &ArrayView2<f32>, params: &InterpolationParams) -> DMatrix<f64> {
+    let n_channels = channel_locations.nrows();
+    let kxx = DMatrix::from_fn(n_channels, n_channels, |i, j| {
+        let regularization = if i == j { 0.01 } else { 0.0 };
+        kriging_distance_kernel(distance(&channel_locations.row(i), &channel_locations.row(j)), params) + regularization
+    });
+    kxx.clone().try_inverse().unwrap_or_else(|| kxx.pseudo_inverse(1e-10).unwrap())
+}
+
+// (n_channels, n_channels) kernel whose row c gives the weights of the original channels read by channel c.
+fn interpolation_kernel(channel_locations: &ArrayView2<f32>, displacement: &ArrayView1<f32>, window_centers: &ArrayView1<f32>,
+    kriging_inverse: Option<&DMatrix<f64>>, params: &InterpolationParams) -> Array2<f32> {
+    let n_channels = channel_locations.nrows();
+    let depth_axis = channel_locations.ncols() - 1;
+    let depths: Vec<f32> = channel_locations.column(depth_axis).to_vec();
+    let depth_min = depths.iter().copied().fold(f32::INFINITY, f32::min);
+    let depth_max = depths.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+
+    let shifted: Array2<f32> = {
+        let mut shifted = channel_locations.to_owned();
+        for c in 0..n_channels {
+            shifted[[c, depth_axis]] += displacement_at(depths[c], displacement, window_centers);
+        }
+        shifted
+    };
+
+    let mut kernel: Array2<f32> = Array2::zeros((n_channels, n_channels));
+    for c in 0..n_channels {
+        let target = shifted.row(c);
+        if params.force_zeros && (target[depth_axis] < depth_min || target[depth_axis] > depth_max) {
+            continue;
+        }
+        let distances: Vec<f32> = (0..n_channels).map(|k| distance(&target, &channel_locations.row(k))).collect();
+
+        match (params.method.as_str(), kriging_inverse) {
+            ("kriging", Some(inverse)) => {
+                for k in 0..n_channels {
+                    kernel[[c, k]] = (0..n_channels).map(|j| kriging_distance_kernel(distances[j], params) * inverse[(j, k)]).sum::<f64>() as f32;
+                }
+            }
+            ("idw", _) => {
+                let mut closest: Vec<usize> = (0..n_channels).collect();
+                closest.sort_by(|&a, &b| distances[a].total_cmp(&distances[b]));
+                closest.truncate(params.num_closest);
+                // a channel on the target position takes all the weight
+                if let Some(&exact) = closest.iter().find(|&&k| distances[k] == 0.0) {
+                    kernel[[c, exact]] = 1.0;
+                } else {
+                    let total: f32 = closest.iter().map(|&k| 1.0 / distances[k]).sum();
+                    for &k in &closest {
+                        kernel[[c, k]] = 1.0 / distances[k] / total;
+                    }
+                }
+            }
+            _ => {
+                let nearest = (0..n_channels).min_by(|&a, &b| distances[a].total_cmp(&distances[b])).unwrap_or(c);
+                kernel[[c, nearest]] = 1.0;
+            }
+        }
+    }
+    kernel
+}
+
+// Linear interpolation of the window displacements at this depth, constant outside the windows.
+fn displacement_at(depth: f32, displacement: &ArrayView1<f32>, window_centers: &ArrayView1<f32>) -> f32 {
+    let n_windows = window_centers.len();
+    if n_windows == 1 || depth <= window_centers[0] {
+        return displacement[0];
+    }
+    if depth >= window_centers[n_windows - 1] {
+        return displacement[n_windows - 1];
+    }
+    let right = window_centers.iter().position(|&center| center > depth).unwrap_or(n_windows - 1);
+    let left = right - 1;
+    let weight = (depth - window_centers[left]) / (window_centers[right] - window_centers[left]);
+    displacement[left] * (1.0 - weight) + displacement[right] * weight
+}
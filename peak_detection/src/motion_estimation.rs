@@ -0,0 +1,239 @@
+use nalgebra::{DMatrix, DVector};
+use ndarray::{Array1, Array2, ArrayView1, Axis};
+use ndarray::parallel::prelude::*;
+use numpy::{IntoPyArray, PyArray1, PyArray2, PyReadonlyArray1};
+use pyo3::prelude::*;
+
+pub(crate) struct MotionParams {
+    pub sampling_frequency: f64,
+    pub bin_duration_s: f64,
+    pub bin_um: f32,
+    pub rigid: bool,
+    pub win_step_um: f32,
+    pub win_sigma_um: f32,
+    pub max_displacement_um: f32,
+    pub corr_threshold: f32,
+    pub weight_with_amplitude: bool,
+}
+
+// Decentralized motion estimation (Varol et al. 2021, spikeinterface "decentralized"): the time x depth activity
+// histogram of the peaks is cross-correlated between every pair of time bins, and the displacement of each bin is the
+// least-squares solution of the pairwise displacements whose correlation exceeds corr_threshold. Non-rigid motion is
+// estimated independently in gaussian windows spaced by win_step_um along the depth.
+// Returns the displacement (n_temporal_bins, n_windows) in um, the temporal bin centers in s and the window centers in um.
+#[pyfunction]
+#[pyo3(signature = (sample_indices, depths, amplitudes, sampling_frequency, bin_duration_s=2.0, bin_um=5.0, rigid=true, win_step_um=50.0,
+    win_sigma_um=150.0, max_displacement_um=100.0, corr_threshold=0.0, weight_with_amplitude=false))]
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn estimate_motion_decentralized_rust<'py>(py: Python<'py>, sample_indices: PyReadonlyArray1<usize>, depths: PyReadonlyArray1<f32>,
+            amplitudes: PyReadonlyArray1<f32>, sampling_frequency: f64, bin_duration_s: f64, bin_um: f32, rigid: bool, win_step_um: f32,
+            win_sigma_um: f32, max_displacement_um: f32, corr_threshold: f32,
+            weight_with_amplitude: bool) -> (Bound<'py,PyArray2<f32>>, Bound<'py,PyArray1<f64>>, Bound<'py,PyArray1<f32>>) {
+    let sample_indices: ArrayView1<usize> = sample_indices.as_array();
+    let depths: ArrayView1<f32> = depths.as_array();
+    let amplitudes: ArrayView1<f32> = amplitudes.as_array();
+    assert!(sample_indices.len() == depths.len() && depths.len() == amplitudes.len(), "sample_indices, depths and amplitudes must have the same length");
+    assert!(bin_duration_s > 0.0 && bin_um > 0.0, "bin_duration_s and bin_um must be positive");
+
+    let params = MotionParams {
+        sampling_frequency, bin_duration_s, bin_um, rigid, win_step_um, win_sigma_um, max_displacement_um, corr_threshold, weight_with_amplitude,
+    };
+
+    let (displacement, temporal_bins, window_centers) = py.detach(|| {
+        let (histogram, temporal_bins, depth_bins) = activity_histogram(&sample_indices, &depths, &amplitudes, &params);
+        let (windows, window_centers) = spatial_windows(&depth_bins, &params);
+        let displacement = estimate_motion_decentralized(&histogram, &windows, &params);
+        (displacement, temporal_bins, window_centers)
+    });
+
+    (displacement.into_pyarray(py), temporal_bins.into_pyarray(py), window_centers.into_pyarray(py))
+}
+
+// Peak counts (or summed absolute amplitudes) per (temporal bin, depth bin), with the bin centers.
+pub(crate) fn activity_histogram(sample_indices: &ArrayView1<usize>, depths: &ArrayView1<f32>, amplitudes: &ArrayView1<f32>,
+    params: &MotionParams) -> (Array2<f32>, Array1<f64>, Array1<f32>) {
+    let bin_size = params.bin_duration_s * params.sampling_frequency;
+    let n_temporal_bins = sample_indices.iter().max().map_or(0, |&last| (last as f64 / bin_size) as usize + 1);
+    let depth_min = depths.iter().copied().fold(f32::INFINITY, f32::min);
+    let depth_max = depths.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let n_depth_bins = if depths.is_empty() { 0 } else { ((depth_max - depth_min) / params.bin_um) as usize + 1 };
+
+    let mut histogram: Array2<f32> = Array2::zeros((n_temporal_bins, n_depth_bins));
+    for ((&sample_ind, &depth), &amplitude) in sample_indices.iter().zip(depths.iter()).zip(amplitudes.iter()) {
+        let t = (sample_ind as f64 / bin_size) as usize;
+        let d = (((depth - depth_min) / params.bin_um) as usize).min(n_depth_bins - 1);
+        histogram[[t, d]] += if params.weight_with_amplitude { amplitude.abs() } else { 1.0 };
+    }
+
+    let temporal_bins: Array1<f64> = (0..n_temporal_bins).map(|t| (t as f64 + 0.5) * params.bin_duration_s).collect();
+    let depth_bins: Array1<f32> = (0..n_depth_bins).map(|d| depth_min + (d as f32 + 0.5) * params.bin_um).collect();
+    (histogram, temporal_bins, depth_bins)
+}
+
+// Weights of each window over the depth bins, (n_windows, n_depth_bins), and the window centers.
+pub(crate) fn spatial_windows(depth_bins: &Array1<f32>, params: &MotionParams) -> (Array2<f32>, Array1<f32>) {
+    let n_depth_bins = depth_bins.len();
+    if params.rigid || n_depth_bins == 0 {
+        let center = if n_depth_bins == 0 { 0.0 } else { (depth_bins[0] + depth_bins[n_depth_bins - 1]) / 2.0 };
+        return (Array2::ones((1, n_depth_bins)), Array1::from(vec![center]));
+    }
+
+    let first = depth_bins[0];
+    let extent = depth_bins[n_depth_bins - 1] - first;
+    let n_windows = (extent / params.win_step_um) as usize + 1;
+    // windows are centered on the probe extent
+    let offset = (extent - (n_windows - 1) as f32 * params.win_step_um) / 2.0;
+    let window_centers: Array1<f32> = (0..n_windows).map(|w| first + offset + w as f32 * params.win_step_um).collect();
+
+    let windows: Array2<f32> = Array2::from_shape_fn((n_windows, n_depth_bins), |(w, d)| {
+        let x = (depth_bins[d] - window_centers[w]) / params.win_sigma_um;
+        (-0.5 * x * x).exp()
+    });
+    (windows, window_centers)
+}
+
+pub(crate) fn estimate_motion_decentralized(histogram: &Array2<f32>, windows: &Array2<f32>, params: &MotionParams) -> Array2<f32> {
+    let n_temporal_bins = histogram.nrows();
+    let max_lag = (params.max_displacement_um / params.bin_um) as isize;
+    let mut displacement: Array2<f32> = Array2::zeros((n_temporal_bins, windows.nrows()));
+
+    for (window, mut window_displacement) in windows.outer_iter().zip(displacement.axis_iter_mut(Axis(1))) {
+        let windowed: Array2<f32> = histogram * &window;
+        let (pairwise_displacement, correlation) = pairwise_displacement(&windowed, max_lag);
+        let solution = decentralize(&pairwise_displacement, &correlation, params.corr_threshold);
+        window_displacement.assign(&solution.mapv(|lag| lag * params.bin_um));
+    }
+
+    displacement
+}
+
+// For every pair of temporal bins, the lag (in depth bins) maximizing the normalized cross-correlation: D[i, j] = lag
+// means that the activity of bin i sits lag bins deeper than in bin j. Computed in parallel over i.
+fn pairwise_displacement(windowed: &Array2<f32>, max_lag: isize) -> (Array2<f32>, Array2<f32>) {
+    let (n_temporal_bins, n_depth_bins) = windowed.dim();
+    let norms: Vec<f32> = windowed.outer_iter().map(|row| row.dot(&row).sqrt()).collect();
+
+    let rows: Vec<(Vec<f32>, Vec<f32>)> = (0..n_temporal_bins).into_par_iter()
+        .map(|i| {
+            let mut lags: Vec<f32> = vec![0.0; n_temporal_bins];
+            let mut correlations: Vec<f32> = vec![0.0; n_temporal_bins];
+            for j in 0..n_temporal_bins {
+                if norms[i] == 0.0 || norms[j] == 0.0 {
+                    continue;
+                }
+                let mut best = (0isize, f32::NEG_INFINITY);
+                for lag in -max_lag..=max_lag {
+                    let mut corr = 0.0;
+                    for d in 0..n_depth_bins as isize {
+                        let shifted = d - lag;
+                        if shifted >= 0 && shifted < n_depth_bins as isize {
+                            corr += windowed[[i, d as usize]] * windowed[[j, shifted as usize]];
+                        }
+                    }
+                    // the smallest lag wins ties
+                    if corr > best.1 || (corr == best.1 && lag.abs() < best.0.abs()) {
+                        best = (lag, corr);
+                    }
+                }
+                lags[j] = best.0 as f32;
+                correlations[j] = best.1 / (norms[i] * norms[j]);
+            }
+            (lags, correlations)
+        })
+        .collect();
+
+    let mut pairwise_displacement: Array2<f32> = Array2::zeros((n_temporal_bins, n_temporal_bins));
+    let mut correlation: Array2<f32> = Array2::zeros((n_temporal_bins, n_temporal_bins));
+    for (i, (lags, correlations)) in rows.into_iter().enumerate() {
+        pairwise_displacement.row_mut(i).assign(&Array1::from(lags));
+        correlation.row_mut(i).assign(&Array1::from(correlations));
+    }
+    (pairwise_displacement, correlation)
+}
+
+// Least-squares p minimizing the sum over the kept pairs of (D[i, j] - (p[i] - p[j]))^2. The pairs only constrain the
+// displacements within each connected component of the kept-pair graph, so every component is solved on its own with
+// a zero mean displacement: the normal equations are L p = b with L the laplacian of the component's pairs, and adding
+// the all-ones matrix fixes the mean.
+fn decentralize(pairwise_displacement: &Array2<f32>, correlation: &Array2<f32>, corr_threshold: f32) -> Array1<f32> {
+    let n = pairwise_displacement.nrows();
+
+    // a pair is kept when the correlation is high in both directions
+    let kept = |i: usize, j: usize| i != j && correlation[[i, j]].min(correlation[[j, i]]) > corr_threshold;
+
+    let mut displacement: Array1<f32> = Array1::zeros(n);
+    let mut visited: Vec<bool> = vec![false; n];
+    for root in 0..n {
+        if visited[root] {
+            continue;
+        }
+        let mut component = vec![root];
+        visited[root] = true;
+        let mut k = 0;
+        while k < component.len() {
+            let i = component[k];
+            let neighbours: Vec<usize> = (0..n).filter(|&j| !visited[j] && kept(i, j)).collect();
+            for j in neighbours {
+                visited[j] = true;
+                component.push(j);
+            }
+            k += 1;
+        }
+
+        // temporal bins without any kept pair stay at zero
+        if component.len() < 2 {
+            continue;
+        }
+        let m = component.len();
+        let mut laplacian = DMatrix::<f64>::from_element(m, m, 1.0);
+        let mut b = DVector::<f64>::zeros(m);
+        for (a, &i) in component.iter().enumerate() {
+            for (c, &j) in component.iter().enumerate() {
+                if kept(i, j) {
+                    laplacian[(a, a)] += 1.0;
+                    laplacian[(a, c)] -= 1.0;
+                    b[a] += (pairwise_displacement[[i, j]] - pairwise_displacement[[j, i]]) as f64 / 2.0;
+                }
+            }
+        }
+        if let Some(solution) = laplacian.lu().solve(&b) {
+            for (a, &i) in component.iter().enumerate() {
+                displacement[i] = solution[a] as f32;
+            }
+        }
+    }
+    displacement
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decentralize_recovers_a_linear_drift() {
+        let n = 5;
+        let pairwise_displacement = Array2::from_shape_fn((n, n), |(i, j)| i as f32 - j as f32);
+        let correlation = Array2::from_elem((n, n), 1.0);
+        let displacement = decentralize(&pairwise_displacement, &correlation, 0.0);
+        for (i, &value) in displacement.iter().enumerate() {
+            assert!((value - (i as f32 - 2.0)).abs() < 1e-4, "bin {i}: {value}");
+        }
+    }
+
+    #[test]
+    fn decentralize_solves_each_component_and_leaves_empty_bins_at_zero() {
+        // bins 0 and 2 are 3 bins apart, bins 1 and 3 are empty so they correlate with nothing
+        let mut pairwise_displacement: Array2<f32> = Array2::zeros((4, 4));
+        pairwise_displacement[[0, 2]] = -3.0;
+        pairwise_displacement[[2, 0]] = 3.0;
+        let mut correlation: Array2<f32> = Array2::zeros((4, 4));
+        correlation[[0, 2]] = 1.0;
+        correlation[[2, 0]] = 1.0;
+
+        let displacement = decentralize(&pairwise_displacement, &correlation, 0.0);
+        let expected = [-1.5, 0.0, 1.5, 0.0];
+        for (&value, &expected) in displacement.iter().zip(expected.iter()) {
+            assert!((value - expected).abs() < 1e-4, "{displacement} != {expected:?}");
+        }
+    }
+}
@@ -0,0 +1,62 @@
+use ndarray::{s, concatenate, Array1, Array2, ArrayView2, Axis};
+use ndarray::parallel::prelude::*;
+use numpy::{IntoPyArray, PyArray1, PyReadonlyArray2};
+use pyo3::prelude::*;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::common_reference::median;
+
+const MAD_TO_STD: f32 = 0.674_489_75;
+
+#[pyfunction]
+#[pyo3(signature = (traces, num_chunks=20, chunk_size=10000, seed=None))]
+pub fn get_noise_levels_rust<'py>(py: Python<'py>, traces: PyReadonlyArray2<f32>, num_chunks: usize, chunk_size: usize,
+            seed: Option<u64>) -> Bound<'py,PyArray1<f32>> {
+    let traces: ArrayView2<f32> = traces.as_array();
+
+    let noise_levels: Array1<f32> = py.detach(|| {
+        let chunks = get_random_data_chunks(&traces, num_chunks, chunk_size, seed);
+        mad_noise_levels(&chunks.view())
+    });
+
+    noise_levels.into_pyarray(py)
+}
+
+// Concatenation of num_chunks random windows of chunk_size samples, the common input of noise and whitening estimates.
+pub(crate) fn get_random_data_chunks(traces: &ArrayView2<f32>, num_chunks: usize, chunk_size: usize, seed: Option<u64>) -> Array2<f32> {
+    let n_samples = traces.nrows();
+    if n_samples <= chunk_size {
+        return traces.to_owned();
+    }
+
+    let starts: Vec<usize> = random_chunk_starts(n_samples, num_chunks, chunk_size, seed);
+
+    let chunks: Vec<ArrayView2<f32>> = starts.iter()
+        .map(|&start| traces.slice(s![start..start + chunk_size, ..]))
+        .collect();
+    concatenate(Axis(0), &chunks).unwrap()
+}
+
+pub(crate) fn random_chunk_starts(n_samples: usize, num_chunks: usize, chunk_size: usize, seed: Option<u64>) -> Vec<usize> {
+    let mut rng: StdRng = match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_os_rng(),
+    };
+    let mut starts: Vec<usize> = (0..num_chunks).map(|_| rng.random_range(0..=n_samples - chunk_size)).collect();
+    starts.sort_unstable();
+    starts
+}
+
+// Per-channel median absolute deviation scaled to a standard deviation.
+pub fn mad_noise_levels(chunks: &ArrayView2<f32>) -> Array1<f32> {
+    let noise_levels: Vec<f32> = chunks.axis_iter(Axis(1)).into_par_iter()
+        .map(|trace| {
+            let mut values: Vec<f32> = trace.to_vec();
+            let center = median(&mut values);
+            values.iter_mut().for_each(|value| *value = (*value - center).abs());
+            median(&mut values) / MAD_TO_STD
+        })
+        .collect();
+    Array1::from(noise_levels)
+}
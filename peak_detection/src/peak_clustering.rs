@@ -0,0 +1,107 @@
+use ndarray::{Array1, ArrayView1, ArrayView2};
+use ndarray::parallel::prelude::*;
+use numpy::{IntoPyArray, PyArray1, PyReadonlyArray1, PyReadonlyArray2};
+use pyo3::prelude::*;
+
+use crate::rust_peak_detection_locally_exclusive_sliding_window::neighbours_mask_to_adjency_list;
+
+pub(crate) struct DensityPeaksParams {
+    pub dc: f32,
+    pub center_distance: f32,
+    pub min_density: f32,
+    pub min_cluster_size: usize,
+}
+
+// Density-peaks clustering (Rodriguez & Laio) of the rows of features, e.g. (x, y, amplitude, svd features) scaled by
+// the caller. Two peaks are only compared when their channels are neighbours, so clusters are split along the probe
+// and the cost stays proportional to the peaks in each neighbourhood. Labels are 0..n_clusters, -1 for noise.
+#[pyfunction]
+#[pyo3(signature = (features, channel_indices, neighbours_mask, dc, center_distance=None, min_density=0.0, min_cluster_size=20))]
+#[allow(clippy::too_many_arguments)]
+pub fn cluster_peaks_density_peaks_rust<'py>(py: Python<'py>, features: PyReadonlyArray2<f32>, channel_indices: PyReadonlyArray1<usize>,
+            neighbours_mask: PyReadonlyArray2<bool>, dc: f32, center_distance: Option<f32>, min_density: f32,
+            min_cluster_size: usize) -> Bound<'py,PyArray1<i64>> {
+    let features: ArrayView2<f32> = features.as_array();
+    let channel_indices: ArrayView1<usize> = channel_indices.as_array();
+    assert_eq!(features.nrows(), channel_indices.len(), "features must have one row per peak");
+    assert!(dc > 0.0, "dc must be positive");
+
+    let adjency_list: Vec<Vec<usize>> = neighbours_mask_to_adjency_list(&neighbours_mask.as_array());
+    let params = DensityPeaksParams { dc, center_distance: center_distance.unwrap_or(2.0 * dc), min_density, min_cluster_size };
+
+    let labels: Array1<i64> = py.detach(|| cluster_peaks_density_peaks(&features, &channel_indices, &adjency_list, &params));
+
+    labels.into_pyarray(py)
+}
+
+pub(crate) fn cluster_peaks_density_peaks(features: &ArrayView2<f32>, channel_indices: &ArrayView1<usize>, adjency_list: &[Vec<usize>],
+    params: &DensityPeaksParams) -> Array1<i64> {
+    let n_peaks = features.nrows();
+
+    let mut peaks_by_channel: Vec<Vec<usize>> = vec![Vec::new(); adjency_list.len()];
+    for (i, &ch) in channel_indices.iter().enumerate() {
+        peaks_by_channel[ch].push(i);
+    }
+    let candidates = |i: usize| adjency_list[channel_indices[i]].iter().flat_map(|&ch| peaks_by_channel[ch].iter().copied());
+    let distance = |i: usize, j: usize| -> f32 {
+        features.row(i).iter().zip(features.row(j).iter()).map(|(a, b)| (a - b) * (a - b)).sum::<f32>().sqrt()
+    };
+
+    // gaussian kernel density, without the peak itself
+    let density: Vec<f32> = (0..n_peaks).into_par_iter()
+        .map(|i| {
+            candidates(i)
+                .filter(|&j| j != i)
+                .map(|j| {
+                    let d = distance(i, j) / params.dc;
+                    (-d * d).exp()
+                })
+                .sum()
+        })
+        .collect();
+
+    // nearest peak of higher density (ties broken by index), None for the local density maxima
+    let higher = |i: usize, j: usize| density[j] > density[i] || (density[j] == density[i] && j < i);
+    let parents: Vec<Option<(usize, f32)>> = (0..n_peaks).into_par_iter()
+        .map(|i| {
+            candidates(i)
+                .filter(|&j| higher(i, j))
+                .map(|j| (j, distance(i, j)))
+                .min_by(|a, b| a.1.total_cmp(&b.1))
+        })
+        .collect();
+
+    let mut order: Vec<usize> = (0..n_peaks).collect();
+    order.sort_by(|&a, &b| density[b].total_cmp(&density[a]).then(a.cmp(&b)));
+
+    // every peak follows its parent, which comes first in decreasing density order
+    let mut labels: Vec<i64> = vec![-1; n_peaks];
+    let mut n_clusters: i64 = 0;
+    for &i in &order {
+        if density[i] < params.min_density {
+            continue;
+        }
+        labels[i] = match parents[i] {
+            Some((parent, delta)) if delta <= params.center_distance => labels[parent],
+            _ => {
+                n_clusters += 1;
+                n_clusters - 1
+            }
+        };
+    }
+
+    let mut sizes: Vec<usize> = vec![0; n_clusters as usize];
+    for &label in labels.iter().filter(|&&label| label >= 0) {
+        sizes[label as usize] += 1;
+    }
+    let mut new_labels: Vec<i64> = vec![-1; n_clusters as usize];
+    let mut n_kept: i64 = 0;
+    for (label, &size) in sizes.iter().enumerate() {
+        if size >= params.min_cluster_size {
+            new_labels[label] = n_kept;
+            n_kept += 1;
+        }
+    }
+
+    labels.iter().map(|&label| if label >= 0 { new_labels[label as usize] } else { -1 }).collect()
+}
@@ -0,0 +1,105 @@
+use std::f64::consts::PI;
+
+use ndarray::{ArrayView1, ArrayView2};
+use numpy::{IntoPyArray, PyArray1, PyReadonlyArray1, PyReadonlyArray2};
+use pyo3::prelude::*;
+
+const SINC_HALF_WIDTH: isize = 4;
+const SINC_UPSAMPLE_FACTOR: usize = 32;
+
+#[pyfunction]
+#[pyo3(signature = (traces, sample_indices, channel_indices, method="parabolic"))]
+pub fn interpolate_peak_times_rust_on_chunk<'py>(py: Python<'py>, traces: PyReadonlyArray2<f32>, sample_indices: PyReadonlyArray1<usize>,
+            channel_indices: PyReadonlyArray1<usize>, method: &str) -> (Bound<'py,PyArray1<f64>>, Bound<'py,PyArray1<f32>>) {
+    assert!(["parabolic", "sinc"].contains(&method), "method must be 'parabolic' or 'sinc'");
+    assert_eq!(sample_indices.len().unwrap(), channel_indices.len().unwrap(), "sample_indices and channel_indices must have the same length");
+
+    let traces: ArrayView2<f32> = traces.as_array();
+    let sample_indices: ArrayView1<usize> = sample_indices.as_array();
+    let channel_indices: ArrayView1<usize> = channel_indices.as_array();
+
+    let peaks: (Vec<f64>, Vec<f32>) = py.detach(
+        || {interpolate_peak_times(&traces, &sample_indices, &channel_indices, method)}
+    );
+
+    (peaks.0.into_pyarray(py), peaks.1.into_pyarray(py))
+}
+
+pub(crate) fn interpolate_peak_times(traces: &ArrayView2<f32>, sample_indices: &ArrayView1<usize>, channel_indices: &ArrayView1<usize>,
+    method: &str) -> (Vec<f64>, Vec<f32>) {
+    sample_indices.iter().zip(channel_indices.iter())
+        .map(|(&sample_ind, &chan_ind)| {
+            let trace = traces.column(chan_ind);
+            let (offset, amplitude) = if method == "sinc" {
+                sinc_peak_offset(&trace, sample_ind)
+            } else {
+                parabolic_peak_offset(&trace, sample_ind)
+            };
+            (sample_ind as f64 + offset, amplitude)
+        })
+        .unzip()
+}
+
+// Vertex of the parabola through the peak sample and its two neighbours.
+pub(crate) fn parabolic_peak_offset(trace: &ArrayView1<f32>, sample_ind: usize) -> (f64, f32) {
+    let amplitude = trace[sample_ind];
+    if sample_ind == 0 || sample_ind + 1 >= trace.len() {
+        return (0.0, amplitude);
+    }
+
+    let before = trace[sample_ind - 1] as f64;
+    let center = amplitude as f64;
+    let after = trace[sample_ind + 1] as f64;
+
+    let curvature = before - 2.0 * center + after;
+    if curvature == 0.0 {
+        return (0.0, amplitude);
+    }
+
+    let offset = (0.5 * (before - after) / curvature).clamp(-0.5, 0.5);
+    (offset, (center - 0.25 * (before - after) * offset) as f32)
+}
+
+// Extremum of the Lanczos-windowed sinc reconstruction, searched on a fine grid within one sample of the peak.
+pub(crate) fn sinc_peak_offset(trace: &ArrayView1<f32>, sample_ind: usize) -> (f64, f32) {
+    let sign = if trace[sample_ind] < 0.0 { -1.0 } else { 1.0 };
+
+    let mut best_offset = 0.0;
+    let mut best_value = trace[sample_ind] as f64;
+    let n_steps = SINC_UPSAMPLE_FACTOR as isize;
+    for step in -n_steps..=n_steps {
+        let offset = step as f64 / SINC_UPSAMPLE_FACTOR as f64;
+        let value = lanczos_interpolate(trace, sample_ind as f64 + offset);
+        if sign * value > sign * best_value {
+            best_offset = offset;
+            best_value = value;
+        }
+    }
+
+    (best_offset, best_value as f32)
+}
+
+fn lanczos_interpolate(trace: &ArrayView1<f32>, position: f64) -> f64 {
+    let n_samples = trace.len() as isize;
+    let floor = position.floor() as isize;
+
+    let mut value = 0.0;
+    for k in floor - SINC_HALF_WIDTH + 1..=floor + SINC_HALF_WIDTH {
+        // samples beyond the chunk edges are replaced by the edge value
+        let k_clipped = k.clamp(0, n_samples - 1) as usize;
+        value += trace[k_clipped] as f64 * lanczos_kernel(position - k as f64);
+    }
+    value
+}
+
+fn lanczos_kernel(x: f64) -> f64 {
+    let half_width = SINC_HALF_WIDTH as f64;
+    if x == 0.0 {
+        1.0
+    } else if x.abs() >= half_width {
+        0.0
+    } else {
+        let pi_x = PI * x;
+        half_width * pi_x.sin() * (pi_x / half_width).sin() / (pi_x * pi_x)
+    }
+}
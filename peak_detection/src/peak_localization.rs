@@ -0,0 +1,383 @@
+use nalgebra::{Matrix4, Vector4};
+use ndarray::{s, Array1, Array2, ArrayView1, ArrayView2};
+use ndarray::parallel::prelude::*;
+use numpy::{IntoPyArray, PyArray1, PyReadonlyArray1, PyReadonlyArray2};
+use pyo3::prelude::*;
+
+use crate::rust_peak_detection_locally_exclusive_sliding_window::neighbours_mask_to_adjency_list;
+
+pub(crate) const FEATURES: [&str; 4] = ["ptp", "mean", "energy", "v_peak"];
+
+pub(crate) struct LocalizationParams {
+    pub nbefore: usize,
+    pub nafter: usize,
+    pub feature: String,
+}
+
+#[pyfunction]
+#[pyo3(signature = (traces, sample_indices, channel_indices, channel_locations, radius_um=75.0, nbefore=20, nafter=20, feature="ptp"))]
+#[allow(clippy::too_many_arguments)]
+pub fn localize_peaks_center_of_mass_rust_on_chunk<'py>(py: Python<'py>, traces: PyReadonlyArray2<f32>, sample_indices: PyReadonlyArray1<usize>,
+            channel_indices: PyReadonlyArray1<usize>, channel_locations: PyReadonlyArray2<f32>, radius_um: f32, nbefore: usize, nafter: usize,
+            feature: &str) -> (Bound<'py,PyArray1<f32>>, Bound<'py,PyArray1<f32>>) {
+    assert!(FEATURES.contains(&feature), "feature must be 'ptp', 'mean', 'energy' or 'v_peak'");
+    assert_eq!(sample_indices.len().unwrap(), channel_indices.len().unwrap(), "sample_indices and channel_indices must have the same length");
+
+    let traces: ArrayView2<f32> = traces.as_array();
+    let sample_indices: ArrayView1<usize> = sample_indices.as_array();
+    let channel_indices: ArrayView1<usize> = channel_indices.as_array();
+    let channel_locations: ArrayView2<f32> = channel_locations.as_array();
+    let local_channels: Vec<Vec<usize>> = radius_adjency_list(&channel_locations, radius_um);
+    let params = LocalizationParams { nbefore, nafter, feature: feature.to_string() };
+
+    let locations: (Vec<f32>, Vec<f32>) = py.detach(
+        || {localize_peaks_center_of_mass(&traces, &sample_indices, &channel_indices, &channel_locations, &local_channels, &params)}
+    );
+
+    (locations.0.into_pyarray(py), locations.1.into_pyarray(py))
+}
+
+#[pyfunction]
+#[pyo3(signature = (traces, sample_indices, channel_indices, channel_locations, radius_um=75.0, max_distance_um=150.0, nbefore=20, nafter=20, feature="ptp"))]
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn localize_peaks_monopolar_triangulation_rust_on_chunk<'py>(py: Python<'py>, traces: PyReadonlyArray2<f32>, sample_indices: PyReadonlyArray1<usize>,
+            channel_indices: PyReadonlyArray1<usize>, channel_locations: PyReadonlyArray2<f32>, radius_um: f32, max_distance_um: f32, nbefore: usize,
+            nafter: usize, feature: &str) -> (Bound<'py,PyArray1<f32>>, Bound<'py,PyArray1<f32>>, Bound<'py,PyArray1<f32>>, Bound<'py,PyArray1<f32>>) {
+    assert!(FEATURES.contains(&feature), "feature must be 'ptp', 'mean', 'energy' or 'v_peak'");
+    assert_eq!(sample_indices.len().unwrap(), channel_indices.len().unwrap(), "sample_indices and channel_indices must have the same length");
+
+    let traces: ArrayView2<f32> = traces.as_array();
+    let sample_indices: ArrayView1<usize> = sample_indices.as_array();
+    let channel_indices: ArrayView1<usize> = channel_indices.as_array();
+    let channel_locations: ArrayView2<f32> = channel_locations.as_array();
+    let local_channels: Vec<Vec<usize>> = radius_adjency_list(&channel_locations, radius_um);
+    let params = LocalizationParams { nbefore, nafter, feature: feature.to_string() };
+
+    let locations: Vec<[f32; 4]> = py.detach(
+        || {localize_peaks_monopolar_triangulation(&traces, &sample_indices, &channel_indices, &channel_locations, &local_channels, &params, max_distance_um)}
+    );
+
+    let column = |k: usize| locations.iter().map(|location| location[k]).collect::<Vec<f32>>().into_pyarray(py);
+    (column(0), column(1), column(2), column(3))
+}
+
+// Grid convolution localization, with the grid templates of the probe computed once and reused for every chunk.
+#[pyclass]
+pub struct GridConvolutionLocalizer {
+    dictionary: GridDictionary,
+    adjency_list: Vec<Vec<usize>>,
+    prototype: Array1<f32>,
+    nbefore: usize,
+    percentile: f32,
+}
+
+#[pymethods]
+impl GridConvolutionLocalizer {
+    #[new]
+    #[pyo3(signature = (channel_locations, neighbours_mask, prototype, nbefore, radius_um=40.0, upsampling_um=5.0, sigma_um=20.0, margin_um=30.0,
+        percentile=5.0))]
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(channel_locations: PyReadonlyArray2<f32>, neighbours_mask: PyReadonlyArray2<bool>, prototype: PyReadonlyArray1<f32>, nbefore: usize,
+        radius_um: f32, upsampling_um: f32, sigma_um: f32, margin_um: f32, percentile: f32) -> Self {
+        assert!(upsampling_um > 0.0, "upsampling_um must be positive");
+        assert!((0.0..=100.0).contains(&percentile), "percentile must be between 0 and 100");
+
+        let channel_locations: ArrayView2<f32> = channel_locations.as_array();
+        let prototype: Array1<f32> = prototype.as_array().to_owned();
+        assert!(nbefore < prototype.len(), "nbefore must be smaller than the prototype length");
+
+        let dictionary = GridDictionary::new(&channel_locations, radius_um, upsampling_um, sigma_um, margin_um);
+        let adjency_list: Vec<Vec<usize>> = neighbours_mask_to_adjency_list(&neighbours_mask.as_array());
+        GridConvolutionLocalizer { dictionary, adjency_list, prototype, nbefore, percentile }
+    }
+
+    pub fn localize_on_chunk<'py>(&self, py: Python<'py>, traces: PyReadonlyArray2<f32>, sample_indices: PyReadonlyArray1<usize>,
+        channel_indices: PyReadonlyArray1<usize>) -> (Bound<'py,PyArray1<f32>>, Bound<'py,PyArray1<f32>>) {
+        assert_eq!(sample_indices.len().unwrap(), channel_indices.len().unwrap(), "sample_indices and channel_indices must have the same length");
+
+        let traces: ArrayView2<f32> = traces.as_array();
+        let sample_indices: ArrayView1<usize> = sample_indices.as_array();
+        let channel_indices: ArrayView1<usize> = channel_indices.as_array();
+        assert_eq!(traces.ncols(), self.adjency_list.len(), "traces must have one column per channel");
+
+        let locations: (Vec<f32>, Vec<f32>) = py.detach(|| {
+            localize_peaks_grid_convolution(&traces, &sample_indices, &channel_indices, &self.adjency_list, &self.dictionary, &self.prototype.view(),
+                self.nbefore, self.percentile)
+        });
+
+        (locations.0.into_pyarray(py), locations.1.into_pyarray(py))
+    }
+}
+
+// Channels closer than radius_um to each channel (the channel itself included).
+pub(crate) fn radius_adjency_list(channel_locations: &ArrayView2<f32>, radius_um: f32) -> Vec<Vec<usize>> {
+    let n_channels = channel_locations.nrows();
+    (0..n_channels)
+        .map(|i| (0..n_channels)
+            .filter(|&j| channel_distance(channel_locations, i, j) <= radius_um)
+            .collect()
+        )
+        .collect()
+}
+
+pub(crate) fn channel_distance(channel_locations: &ArrayView2<f32>, i: usize, j: usize) -> f32 {
+    let dx = channel_locations[[i, 0]] - channel_locations[[j, 0]];
+    let dy = channel_locations[[i, 1]] - channel_locations[[j, 1]];
+    dx.hypot(dy)
+}
+
+// Per local channel amplitude of the window [sample_ind - nbefore, sample_ind + nafter), clipped to the chunk.
+pub(crate) fn local_amplitudes(traces: &ArrayView2<f32>, sample_ind: usize, local_channels: &[usize], params: &LocalizationParams) -> Array1<f32> {
+    let start = sample_ind.saturating_sub(params.nbefore);
+    let end = (sample_ind + params.nafter).min(traces.nrows());
+
+    local_channels.iter()
+        .map(|&ch| {
+            let window = traces.slice(s![start..end, ch]);
+            match params.feature.as_str() {
+                "ptp" => {
+                    let max = window.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+                    let min = window.iter().copied().fold(f32::INFINITY, f32::min);
+                    max - min
+                }
+                "mean" => window.mean().unwrap_or(0.0),
+                "energy" => window.dot(&window).sqrt(),
+                _ => traces[[sample_ind, ch]],
+            }
+        })
+        .collect()
+}
+
+pub(crate) fn localize_peaks_center_of_mass(traces: &ArrayView2<f32>, sample_indices: &ArrayView1<usize>, channel_indices: &ArrayView1<usize>,
+    channel_locations: &ArrayView2<f32>, local_channels: &[Vec<usize>], params: &LocalizationParams) -> (Vec<f32>, Vec<f32>) {
+    let peaks: Vec<(usize, usize)> = sample_indices.iter().copied().zip(channel_indices.iter().copied()).collect();
+
+    peaks.par_iter()
+        .map(|&(sample_ind, chan_ind)| {
+            let channels = &local_channels[chan_ind];
+            let amplitudes = local_amplitudes(traces, sample_ind, channels, params).mapv(f32::abs);
+            center_of_mass(&amplitudes, channels, channel_locations)
+                .unwrap_or((channel_locations[[chan_ind, 0]], channel_locations[[chan_ind, 1]]))
+        })
+        .unzip()
+}
+
+pub(crate) fn center_of_mass(weights: &Array1<f32>, channels: &[usize], channel_locations: &ArrayView2<f32>) -> Option<(f32, f32)> {
+    let total: f32 = weights.sum();
+    if total <= 0.0 || !total.is_finite() {
+        return None;
+    }
+    let x: f32 = channels.iter().zip(weights.iter()).map(|(&ch, &w)| w * channel_locations[[ch, 0]]).sum();
+    let y: f32 = channels.iter().zip(weights.iter()).map(|(&ch, &w)| w * channel_locations[[ch, 1]]).sum();
+    Some((x / total, y / total))
+}
+
+// Fits, for each peak, a point source (x, y, z, alpha) whose amplitude on a contact at distance d is alpha / d.
+pub(crate) fn localize_peaks_monopolar_triangulation(traces: &ArrayView2<f32>, sample_indices: &ArrayView1<usize>, channel_indices: &ArrayView1<usize>,
+    channel_locations: &ArrayView2<f32>, local_channels: &[Vec<usize>], params: &LocalizationParams, max_distance_um: f32) -> Vec<[f32; 4]> {
+    let peaks: Vec<(usize, usize)> = sample_indices.iter().copied().zip(channel_indices.iter().copied()).collect();
+
+    peaks.par_iter()
+        .map(|&(sample_ind, chan_ind)| {
+            let channels = &local_channels[chan_ind];
+            let amplitudes = local_amplitudes(traces, sample_ind, channels, params).mapv(f32::abs);
+            let contacts: Vec<(f64, f64)> = channels.iter()
+                .map(|&ch| (channel_locations[[ch, 0]] as f64, channel_locations[[ch, 1]] as f64))
+                .collect();
+            let amplitudes: Vec<f64> = amplitudes.iter().map(|&amplitude| amplitude as f64).collect();
+            solve_monopolar_triangulation(&amplitudes, &contacts, max_distance_um as f64)
+                .map_or([f32::NAN; 4], |solution| solution.map(|value| value as f32))
+        })
+        .collect()
+}
+
+const INITIAL_Z: f64 = 20.0;
+const MAX_ITERATIONS: usize = 100;
+
+// Bounded Levenberg-Marquardt on the amplitude residuals, started from the center of mass as in spikeinterface.
+fn solve_monopolar_triangulation(amplitudes: &[f64], contacts: &[(f64, f64)], max_distance_um: f64) -> Option<[f64; 4]> {
+    let total: f64 = amplitudes.iter().sum();
+    if total <= 0.0 || !total.is_finite() {
+        return None;
+    }
+    let (ind_max, &max_amplitude) = amplitudes.iter().enumerate().max_by(|a, b| a.1.total_cmp(b.1))?;
+
+    let com_x = amplitudes.iter().zip(contacts).map(|(a, c)| a * c.0).sum::<f64>() / total;
+    let com_y = amplitudes.iter().zip(contacts).map(|(a, c)| a * c.1).sum::<f64>() / total;
+    let initial_alpha = ((com_x - contacts[ind_max].0).powi(2) + (com_y - contacts[ind_max].1).powi(2) + INITIAL_Z * INITIAL_Z).sqrt() * max_amplitude;
+
+    let lower = Vector4::new(com_x - max_distance_um, com_y - max_distance_um, 1.0, 0.0);
+    let upper = Vector4::new(com_x + max_distance_um, com_y + max_distance_um, max_distance_um * 10.0, max_amplitude * max_distance_um);
+    let mut params = Vector4::new(com_x, com_y, INITIAL_Z, initial_alpha).sup(&lower).inf(&upper);
+
+    let mut cost = monopolar_cost(&params, amplitudes, contacts);
+    let mut damping = 1e-3;
+    for _ in 0..MAX_ITERATIONS {
+        let (jtj, jtr) = monopolar_normal_equations(&params, amplitudes, contacts);
+        let mut improved = false;
+
+        while damping < 1e12 {
+            let mut system = jtj;
+            for k in 0..4 {
+                system[(k, k)] += damping * jtj[(k, k)].max(1e-12);
+            }
+            let Some(step) = system.lu().solve(&(-jtr)) else {
+                damping *= 10.0;
+                continue;
+            };
+            let candidate = (params + step).sup(&lower).inf(&upper);
+            let candidate_cost = monopolar_cost(&candidate, amplitudes, contacts);
+            if candidate_cost < cost {
+                let converged = (candidate - params).norm() <= 1e-8 * (params.norm() + 1e-8) || cost - candidate_cost <= 1e-12 * cost;
+                params = candidate;
+                cost = candidate_cost;
+                damping = (damping / 10.0).max(1e-12);
+                improved = !converged;
+                break;
+            }
+            damping *= 10.0;
+        }
+
+        if !improved {
+            break;
+        }
+    }
+
+    Some([params[0], params[1], params[2], params[3]])
+}
+
+fn monopolar_distance(params: &Vector4<f64>, contact: &(f64, f64)) -> f64 {
+    ((contact.0 - params[0]).powi(2) + (contact.1 - params[1]).powi(2) + params[2] * params[2]).sqrt()
+}
+
+fn monopolar_cost(params: &Vector4<f64>, amplitudes: &[f64], contacts: &[(f64, f64)]) -> f64 {
+    amplitudes.iter().zip(contacts)
+        .map(|(amplitude, contact)| (amplitude - params[3] / monopolar_distance(params, contact)).powi(2))
+        .sum()
+}
+
+fn monopolar_normal_equations(params: &Vector4<f64>, amplitudes: &[f64], contacts: &[(f64, f64)]) -> (Matrix4<f64>, Vector4<f64>) {
+    let mut jtj = Matrix4::zeros();
+    let mut jtr = Vector4::zeros();
+    for (amplitude, contact) in amplitudes.iter().zip(contacts) {
+        let distance = monopolar_distance(params, contact);
+        let distance_cubed = distance.powi(3);
+        let residual = amplitude - params[3] / distance;
+        let jacobian = Vector4::new(
+            -params[3] * (contact.0 - params[0]) / distance_cubed,
+            -params[3] * (contact.1 - params[1]) / distance_cubed,
+            params[3] * params[2] / distance_cubed,
+            -1.0 / distance,
+        );
+        jtj += jacobian * jacobian.transpose();
+        jtr += jacobian * residual;
+    }
+    (jtj, jtr)
+}
+
+// Virtual templates on a regular grid covering the probe: each one predicts a gaussian decay of the amplitude
+// with the distance to the contacts, restricted to the contacts within radius_um and normalised to unit norm.
+pub(crate) struct GridDictionary {
+    positions: Vec<(f32, f32)>,
+    weights: Array2<f32>,
+    nearest_templates: Vec<Vec<usize>>,
+}
+
+impl GridDictionary {
+    pub(crate) fn new(channel_locations: &ArrayView2<f32>, radius_um: f32, upsampling_um: f32, sigma_um: f32, margin_um: f32) -> Self {
+        let n_channels = channel_locations.nrows();
+        let column_range = |k: usize| {
+            let column = channel_locations.column(k);
+            let min = column.iter().copied().fold(f32::INFINITY, f32::min) - margin_um;
+            let max = column.iter().copied().fold(f32::NEG_INFINITY, f32::max) + margin_um;
+            (min, ((max - min) / upsampling_um).floor() as usize + 1)
+        };
+        let (x_min, n_x) = column_range(0);
+        let (y_min, n_y) = column_range(1);
+
+        let positions: Vec<(f32, f32)> = (0..n_y)
+            .flat_map(|j| (0..n_x).map(move |i| (x_min + i as f32 * upsampling_um, y_min + j as f32 * upsampling_um)))
+            .collect();
+
+        let mut weights: Array2<f32> = Array2::zeros((positions.len(), n_channels));
+        let mut nearest_templates: Vec<Vec<usize>> = vec![Vec::new(); n_channels];
+        for (t, &(x, y)) in positions.iter().enumerate() {
+            for ch in 0..n_channels {
+                let distance = (channel_locations[[ch, 0]] - x).hypot(channel_locations[[ch, 1]] - y);
+                if distance <= radius_um {
+                    weights[[t, ch]] = (-distance * distance / (2.0 * sigma_um * sigma_um)).exp();
+                    nearest_templates[ch].push(t);
+                }
+            }
+            let mut row = weights.row_mut(t);
+            let norm = row.dot(&row).sqrt();
+            if norm > 0.0 {
+                row /= norm;
+            }
+        }
+
+        GridDictionary { positions, weights, nearest_templates }
+    }
+}
+
+// Projects the snippet of each local channel on the temporal prototype, scores the grid templates around the peak
+// channel by their dot product with these amplitudes, and averages the positions of the best scoring templates.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn localize_peaks_grid_convolution(traces: &ArrayView2<f32>, sample_indices: &ArrayView1<usize>, channel_indices: &ArrayView1<usize>,
+    adjency_list: &[Vec<usize>], dictionary: &GridDictionary, prototype: &ArrayView1<f32>, nbefore: usize, percentile: f32) -> (Vec<f32>, Vec<f32>) {
+    let peaks: Vec<(usize, usize)> = sample_indices.iter().copied().zip(channel_indices.iter().copied()).collect();
+    let prototype_norm = prototype.dot(prototype).sqrt().max(f32::EPSILON);
+    let nafter = prototype.len() - nbefore;
+
+    peaks.par_iter()
+        .map(|&(sample_ind, chan_ind)| {
+            let fallback = dictionary_fallback(dictionary, chan_ind);
+            if sample_ind < nbefore || sample_ind + nafter > traces.nrows() {
+                return fallback;
+            }
+
+            let channels = &adjency_list[chan_ind];
+            let amplitudes: Vec<f32> = channels.iter()
+                .map(|&ch| traces.slice(s![sample_ind - nbefore..sample_ind + nafter, ch]).dot(prototype) / prototype_norm)
+                .collect();
+
+            let templates = &dictionary.nearest_templates[chan_ind];
+            let mut scores: Vec<f32> = templates.iter()
+                .map(|&t| {
+                    let score: f32 = channels.iter().zip(&amplitudes).map(|(&ch, &amplitude)| dictionary.weights[[t, ch]] * amplitude).sum();
+                    score.max(0.0)
+                })
+                .collect();
+
+            let threshold = percentile_of(&scores, percentile);
+            scores.iter_mut().filter(|score| **score < threshold).for_each(|score| *score = 0.0);
+            let total: f32 = scores.iter().sum();
+            if total <= 0.0 {
+                return fallback;
+            }
+            let x: f32 = templates.iter().zip(&scores).map(|(&t, &score)| score * dictionary.positions[t].0).sum();
+            let y: f32 = templates.iter().zip(&scores).map(|(&t, &score)| score * dictionary.positions[t].1).sum();
+            (x / total, y / total)
+        })
+        .unzip()
+}
+
+fn dictionary_fallback(dictionary: &GridDictionary, chan_ind: usize) -> (f32, f32) {
+    // the best template for a flat snippet is the one closest to the peak channel, i.e. with the largest weight
+    dictionary.nearest_templates[chan_ind].iter()
+        .max_by(|&&a, &&b| dictionary.weights[[a, chan_ind]].total_cmp(&dictionary.weights[[b, chan_ind]]))
+        .map_or((f32::NAN, f32::NAN), |&t| dictionary.positions[t])
+}
+
+// Linear interpolation between the closest ranks, as numpy.percentile.
+pub(crate) fn percentile_of(values: &[f32], percentile: f32) -> f32 {
+    if values.is_empty() {
+        return f32::NAN;
+    }
+    let mut sorted: Vec<f32> = values.to_vec();
+    sorted.sort_by(f32::total_cmp);
+    let rank = percentile / 100.0 * (sorted.len() - 1) as f32;
+    let lower = rank.floor() as usize;
+    let upper = rank.ceil() as usize;
+    sorted[lower] + (sorted[upper] - sorted[lower]) * (rank - lower as f32)
+}
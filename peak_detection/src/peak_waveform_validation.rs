@@ -0,0 +1,154 @@
+use ndarray::{ArrayView1, ArrayView2};
+use numpy::{IntoPyArray, PyArray1, PyReadonlyArray1, PyReadonlyArray2};
+use pyo3::prelude::*;
+
+pub(crate) struct WaveformShapeBounds {
+    pub min_width: Option<f32>,
+    pub max_width: Option<f32>,
+    pub min_duration: Option<f32>,
+    pub max_duration: Option<f32>,
+    pub duration_search_size: usize,
+}
+
+#[pyfunction]
+#[pyo3(signature = (traces, sample_indices, channel_indices, min_width=None, max_width=None, min_duration=None, max_duration=None, duration_search_size=0))]
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn validate_peak_waveforms_rust_on_chunk<'py>(py: Python<'py>, traces: PyReadonlyArray2<f32>, sample_indices: PyReadonlyArray1<usize>,
+            channel_indices: PyReadonlyArray1<usize>, min_width: Option<f32>, max_width: Option<f32>, min_duration: Option<f32>,
+            max_duration: Option<f32>, duration_search_size: usize)
+            -> (Bound<'py,PyArray1<usize>>, Bound<'py,PyArray1<usize>>, Bound<'py,PyArray1<f32>>, Bound<'py,PyArray1<f32>>) {
+    assert_eq!(sample_indices.len().unwrap(), channel_indices.len().unwrap(), "sample_indices and channel_indices must have the same length");
+    // without a search window every duration is NaN, which would reject every peak
+    assert!(duration_search_size > 0 || (min_duration.is_none() && max_duration.is_none()),
+        "duration_search_size must be positive when min_duration or max_duration is given");
+
+    let traces: ArrayView2<f32> = traces.as_array();
+    let sample_indices: ArrayView1<usize> = sample_indices.as_array();
+    let channel_indices: ArrayView1<usize> = channel_indices.as_array();
+    let bounds = WaveformShapeBounds { min_width, max_width, min_duration, max_duration, duration_search_size };
+
+    let peaks: (Vec<usize>, Vec<usize>, Vec<f32>, Vec<f32>) = py.detach(
+        || {validate_peak_waveforms(&traces, &sample_indices, &channel_indices, &bounds)}
+    );
+
+    (peaks.0.into_pyarray(py), peaks.1.into_pyarray(py), peaks.2.into_pyarray(py), peaks.3.into_pyarray(py))
+}
+
+pub(crate) fn validate_peak_waveforms(traces: &ArrayView2<f32>, sample_indices: &ArrayView1<usize>, channel_indices: &ArrayView1<usize>,
+    bounds: &WaveformShapeBounds) -> (Vec<usize>, Vec<usize>, Vec<f32>, Vec<f32>) {
+
+    let mut kept_samples: Vec<usize> = Vec::with_capacity(sample_indices.len());
+    let mut kept_channels: Vec<usize> = Vec::with_capacity(sample_indices.len());
+    let mut widths: Vec<f32> = Vec::with_capacity(sample_indices.len());
+    let mut durations: Vec<f32> = Vec::with_capacity(sample_indices.len());
+
+    for (&sample_ind, &chan_ind) in sample_indices.iter().zip(channel_indices.iter()) {
+        let trace = traces.column(chan_ind);
+
+        let width = half_amplitude_width(&trace, sample_ind);
+        let duration = peak_to_opposite_duration(&trace, sample_ind, bounds.duration_search_size);
+
+        if !within_bounds(width, bounds.min_width, bounds.max_width) || !within_bounds(duration, bounds.min_duration, bounds.max_duration) {
+            continue;
+        }
+
+        kept_samples.push(sample_ind);
+        kept_channels.push(chan_ind);
+        widths.push(width);
+        durations.push(duration);
+    }
+
+    (kept_samples, kept_channels, widths, durations)
+}
+
+fn within_bounds(value: f32, lower: Option<f32>, upper: Option<f32>) -> bool {
+    // an unmeasurable value (NaN) only passes when no bound is requested
+    if lower.is_none() && upper.is_none() {
+        return true;
+    }
+    if value.is_nan() {
+        return false;
+    }
+    lower.is_none_or(|lower| value >= lower) && upper.is_none_or(|upper| value <= upper)
+}
+
+// Full width at half amplitude in (fractional) samples, with linear interpolation of both crossings.
+// Returns NaN when the waveform does not come back under half amplitude inside the chunk.
+pub(crate) fn half_amplitude_width(trace: &ArrayView1<f32>, sample_ind: usize) -> f32 {
+    let amplitude = trace[sample_ind];
+    let sign = if amplitude < 0.0 { -1.0 } else { 1.0 };
+    let half = sign * amplitude / 2.0;
+
+    let mut left = f32::NAN;
+    let mut i = sample_ind;
+    while i > 0 {
+        let previous = sign * trace[i - 1];
+        if previous <= half {
+            let current = sign * trace[i];
+            left = (i - 1) as f32 + (half - previous) / (current - previous);
+            break;
+        }
+        i -= 1;
+    }
+
+    let mut right = f32::NAN;
+    let mut i = sample_ind;
+    while i + 1 < trace.len() {
+        let next = sign * trace[i + 1];
+        if next <= half {
+            let current = sign * trace[i];
+            right = i as f32 + (current - half) / (current - next);
+            break;
+        }
+        i += 1;
+    }
+
+    right - left
+}
+
+// Distance in samples between the peak and the largest opposite-sign excursion that follows it
+// (trough-to-peak for negative spikes, peak-to-trough for positive ones).
+pub(crate) fn peak_to_opposite_duration(trace: &ArrayView1<f32>, sample_ind: usize, search_size: usize) -> f32 {
+    let sign = if trace[sample_ind] < 0.0 { -1.0 } else { 1.0 };
+    let end = (sample_ind + search_size + 1).min(trace.len());
+    if sample_ind + 1 >= end {
+        return f32::NAN;
+    }
+
+    let mut best_ind = sample_ind + 1;
+    for i in sample_ind + 1..end {
+        if -sign * trace[i] > -sign * trace[best_ind] {
+            best_ind = i;
+        }
+    }
+
+    (best_ind - sample_ind) as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::Array2;
+
+    #[test]
+    fn duration_bounds_keep_a_spike_and_reject_a_slow_deflection() {
+        let n_samples = 400;
+        let gaussian = |t: usize, center: f32, sigma: f32| (-((t as f32 - center) / sigma).powi(2) / 2.0).exp();
+        // channel 0: biphasic spike, trough at 100 and rebound 10 samples later; channel 1: slow LFP-like deflection
+        let traces: Array2<f32> = Array2::from_shape_fn((n_samples, 2), |(t, ch)| {
+            if ch == 0 { -100.0 * gaussian(t, 100.0, 2.0) + 30.0 * gaussian(t, 110.0, 4.0) } else { -100.0 * gaussian(t, 200.0, 80.0) }
+        });
+        let sample_indices = ndarray::arr1(&[100, 200]);
+        let channel_indices = ndarray::arr1(&[0, 1]);
+
+        let bounds = WaveformShapeBounds { min_width: None, max_width: None, min_duration: Some(3.0), max_duration: Some(30.0), duration_search_size: 40 };
+        let (kept_samples, kept_channels, _, durations) = validate_peak_waveforms(&traces.view(), &sample_indices.view(), &channel_indices.view(), &bounds);
+        assert_eq!((kept_samples, kept_channels), (vec![100], vec![0]));
+        assert_eq!(durations, vec![10.0]);
+
+        let bounds = WaveformShapeBounds { min_width: Some(2.0), max_width: Some(20.0), min_duration: None, max_duration: None, duration_search_size: 0 };
+        let (kept_samples, _, widths, _) = validate_peak_waveforms(&traces.view(), &sample_indices.view(), &channel_indices.view(), &bounds);
+        assert_eq!(kept_samples, vec![100]);
+        assert!(widths[0] > 4.0 && widths[0] < 6.0, "width {}", widths[0]);
+    }
+}
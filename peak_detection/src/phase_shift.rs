@@ -0,0 +1,97 @@
+use std::f64::consts::PI;
+
+use ndarray::{s, Array1, Array2, ArrayView1, ArrayView2, ArrayViewMut2, Axis};
+use ndarray::parallel::prelude::*;
+use numpy::{Complex64, IntoPyArray, PyArray1, PyArray2, PyReadonlyArray1, PyReadonlyArray2};
+use pyo3::prelude::*;
+use realfft::RealFftPlanner;
+
+// SpikeGLX probe types of the Neuropixels 2.0 family (imroTbl header); every other type samples like a 1.0 probe.
+const NP2_PROBE_TYPES: [usize; 6] = [21, 24, 2003, 2004, 2013, 2014];
+
+#[pyfunction]
+#[pyo3(signature = (probe_type, channel_indices, num_readout_channels=384))]
+pub fn neuropixels_sample_shifts_rust<'py>(py: Python<'py>, probe_type: usize, channel_indices: Vec<usize>,
+            num_readout_channels: usize) -> Bound<'py,PyArray1<f32>> {
+    let all_shifts: Array1<f32> = neuropixels_sample_shifts(probe_type, num_readout_channels);
+    let sample_shifts: Array1<f32> = channel_indices.iter().map(|&ch| all_shifts[ch]).collect();
+    sample_shifts.into_pyarray(py)
+}
+
+#[pyfunction]
+pub fn phase_shift_rust_on_chunk<'py>(py: Python<'py>, traces: PyReadonlyArray2<f32>, sample_shifts: PyReadonlyArray1<f32>,
+            margin: usize) -> Bound<'py,PyArray2<f32>> {
+    let traces: ArrayView2<f32> = traces.as_array();
+    let sample_shifts: ArrayView1<f32> = sample_shifts.as_array();
+    assert_eq!(sample_shifts.len(), traces.ncols(), "sample_shifts must have one entry per channel");
+    assert!(traces.nrows() >= 2 * margin, "the chunk is shorter than its margins");
+
+    let shifted: Array2<f32> = py.detach(|| {
+        let mut shifted = traces.to_owned();
+        phase_shift_in_place(&mut shifted.view_mut(), &sample_shifts, margin);
+        let n_samples = shifted.nrows();
+        shifted.slice(s![margin..n_samples - margin, ..]).to_owned()
+    });
+
+    shifted.into_pyarray(py)
+}
+
+// Fraction of a sample by which each readout channel lags the first one: channels are digitised by ADCs that
+// each convert a fixed number of channels in turn, two ADCs per group of interleaved even/odd channels.
+pub(crate) fn neuropixels_sample_shifts(probe_type: usize, num_readout_channels: usize) -> Array1<f32> {
+    let (adc_channels, n_cycles) = if NP2_PROBE_TYPES.contains(&probe_type) { (16, 16) } else { (12, 13) };
+
+    (0..num_readout_channels)
+        .map(|ch| {
+            let order_in_adc = (ch % (2 * adc_channels)) / 2;
+            order_in_adc as f32 / n_cycles as f32
+        })
+        .collect()
+}
+
+// Applies a per-channel fractional delay in the frequency domain. The margins are tapered with a Hann ramp to limit
+// the wrap-around of the circular shift; they should be discarded by the caller afterwards.
+pub(crate) fn phase_shift_in_place(traces: &mut ArrayViewMut2<f32>, sample_shifts: &ArrayView1<f32>, margin: usize) {
+    let n_samples = traces.nrows();
+    if n_samples == 0 {
+        return;
+    }
+
+    let mut planner = RealFftPlanner::<f64>::new();
+    let forward = planner.plan_fft_forward(n_samples);
+    let inverse = planner.plan_fft_inverse(n_samples);
+    let n_frequencies = n_samples / 2 + 1;
+    // highest frequency in radians per sample, as numpy's rfftfreq
+    let omega_max = if n_samples.is_multiple_of(2) { PI } else { PI * (n_samples - 1) as f64 / n_samples as f64 };
+    let taper: Vec<f64> = (0..margin).map(|i| 0.5 - 0.5 * (PI * i as f64 / margin as f64).cos()).collect();
+
+    traces.axis_iter_mut(Axis(1)).into_par_iter().enumerate().for_each_init(
+        || (forward.make_input_vec(), forward.make_output_vec()),
+        |(signal, spectrum), (ch, mut trace)| {
+            let shift = sample_shifts[ch] as f64;
+            for (value, &sample) in signal.iter_mut().zip(trace.iter()) {
+                *value = sample as f64;
+            }
+            for (i, &weight) in taper.iter().enumerate() {
+                signal[i] *= weight;
+                signal[n_samples - 1 - i] *= weight;
+            }
+
+            forward.process(signal, spectrum).unwrap();
+            for (k, bin) in spectrum.iter_mut().enumerate() {
+                let omega = if n_frequencies > 1 { omega_max * k as f64 / (n_frequencies - 1) as f64 } else { 0.0 };
+                *bin *= Complex64::from_polar(1.0 / n_samples as f64, -omega * shift);
+            }
+            // the DC and Nyquist bins of a real signal are real; irfft ignores their imaginary part
+            spectrum[0].im = 0.0;
+            if n_samples.is_multiple_of(2) {
+                spectrum[n_frequencies - 1].im = 0.0;
+            }
+            inverse.process(spectrum, signal).unwrap();
+
+            for (sample, &value) in trace.iter_mut().zip(signal.iter()) {
+                *sample = value as f32;
+            }
+        }
+    );
+}
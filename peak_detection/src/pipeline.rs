@@ -0,0 +1,323 @@
+use ndarray::{s, Array1, Array2, Array3, ArrayView1, ArrayView2};
+use numpy::{IntoPyArray, PyArray1, PyArray2, PyArray3, PyReadonlyArray1, PyReadonlyArray2};
+use pyo3::prelude::*;
+
+use crate::bandpass_filter::{filter_traces_in_place, Sos};
+use crate::common_reference::{common_reference_in_place, reference_channels, ReferenceChannels};
+use crate::motion_correction::{interpolate_motion, InterpolationParams};
+use crate::peak_localization::{localize_peaks_center_of_mass, localize_peaks_monopolar_triangulation, radius_adjency_list, LocalizationParams, FEATURES};
+use crate::phase_shift::phase_shift_in_place;
+use crate::rust_peak_detection_locally_exclusive_sliding_window::{detect_peaks_locally_exclusive, enabled_channels, exclude_channels, neighbours_mask_to_adjency_list};
+use crate::svd_features::extract_svd_features;
+use crate::whitening::apply_whitening;
+
+pub(crate) enum Stage {
+    BandpassFilter { sos: Sos, zero_phase: bool, margin: usize },
+    CommonReference { reference_channels: ReferenceChannels, operator: String },
+    PhaseShift { sample_shifts: Array1<f32>, margin: usize },
+    Whitening { whitening_matrix: Array2<f32>, mean: Array1<f32> },
+    MotionCorrection { motion: Motion, params: InterpolationParams },
+}
+
+// Motion estimated by estimate_motion_decentralized_rust, with what is needed to place the chunks in time.
+pub(crate) struct Motion {
+    channel_locations: Array2<f32>,
+    displacement: Array2<f32>,
+    temporal_bins: Array1<f64>,
+    window_centers: Array1<f32>,
+    sampling_frequency: f64,
+}
+
+impl Stage {
+    fn margin(&self) -> usize {
+        match self {
+            Stage::BandpassFilter { margin, .. } | Stage::PhaseShift { margin, .. } => *margin,
+            Stage::CommonReference { .. } | Stage::Whitening { .. } | Stage::MotionCorrection { .. } => 0,
+        }
+    }
+}
+
+pub(crate) struct DetectionStage {
+    peak_sign: String,
+    abs_thresholds: Array1<f32>,
+    exclude_sweep_size: usize,
+    adjency_list: Vec<Vec<usize>>,
+    merge_both_signs: bool,
+}
+
+pub(crate) struct LocalizationStage {
+    method: String,
+    max_distance_um: f32,
+    channel_locations: Array2<f32>,
+    local_channels: Vec<Vec<usize>>,
+    params: LocalizationParams,
+}
+
+pub(crate) struct SvdFeaturesStage {
+    components: Array2<f32>,
+    nbefore: usize,
+    adjency_list: Vec<Vec<usize>>,
+}
+
+// Preprocessing stages run in declaration order on each chunk, each one consuming its own margin on both sides,
+// followed by the locally-exclusive detection. Chunks must come with get_trace_margin() samples on each side, and with
+// the recording sample of their first row (start_sample, margins included) when the motion is corrected.
+#[pyclass]
+pub struct PeakDetectionPipeline {
+    num_channels: usize,
+    stages: Vec<Stage>,
+    detection: Option<DetectionStage>,
+    localization: Option<LocalizationStage>,
+    svd_features: Option<SvdFeaturesStage>,
+}
+
+#[pymethods]
+impl PeakDetectionPipeline {
+    #[new]
+    pub fn new(num_channels: usize) -> Self {
+        PeakDetectionPipeline { num_channels, stages: Vec::new(), detection: None, localization: None, svd_features: None }
+    }
+
+    #[pyo3(signature = (sos, margin, direction="forward-backward"))]
+    pub fn add_bandpass_filter(&mut self, sos: PyReadonlyArray2<f64>, margin: usize, direction: &str) {
+        assert!(["forward", "forward-backward"].contains(&direction), "direction must be 'forward' or 'forward-backward'");
+        assert_eq!(sos.as_array().ncols(), 6, "sos must have shape (n_sections, 6)");
+        self.stages.push(Stage::BandpassFilter { sos: sos.as_array().to_owned(), zero_phase: direction == "forward-backward", margin });
+    }
+
+    #[pyo3(signature = (reference="global", operator="median", groups=None, neighbours_mask=None))]
+    pub fn add_common_reference(&mut self, reference: &str, operator: &str, groups: Option<Vec<usize>>, neighbours_mask: Option<PyReadonlyArray2<bool>>) {
+        assert!(["median", "average"].contains(&operator), "operator must be 'median' or 'average'");
+        let reference_channels = reference_channels(reference, self.num_channels, groups, neighbours_mask.as_ref().map(|mask| mask.as_array()));
+        self.stages.push(Stage::CommonReference { reference_channels, operator: operator.to_string() });
+    }
+
+    pub fn add_phase_shift(&mut self, sample_shifts: PyReadonlyArray1<f32>, margin: usize) {
+        assert_eq!(sample_shifts.as_array().len(), self.num_channels, "sample_shifts must have one entry per channel");
+        self.stages.push(Stage::PhaseShift { sample_shifts: sample_shifts.as_array().to_owned(), margin });
+    }
+
+    pub fn add_whitening(&mut self, whitening_matrix: PyReadonlyArray2<f32>, mean: PyReadonlyArray1<f32>) {
+        assert_eq!(whitening_matrix.as_array().dim(), (self.num_channels, self.num_channels), "whitening_matrix must be (num_channels, num_channels)");
+        self.stages.push(Stage::Whitening { whitening_matrix: whitening_matrix.as_array().to_owned(), mean: mean.as_array().to_owned() });
+    }
+
+    // Same interpolation as interpolate_motion_rust_on_chunk.
+    #[pyo3(signature = (channel_locations, displacement, temporal_bins, window_centers, sampling_frequency, method="kriging", sigma_um=20.0, p=1.0,
+        num_closest=3, border_mode="force_extrapolate"))]
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_motion_correction(&mut self, channel_locations: PyReadonlyArray2<f32>, displacement: PyReadonlyArray2<f32>, temporal_bins: PyReadonlyArray1<f64>,
+            window_centers: PyReadonlyArray1<f32>, sampling_frequency: f64, method: &str, sigma_um: f32, p: f32, num_closest: usize, border_mode: &str) {
+        let params = InterpolationParams::new(method, sigma_um, p, num_closest, border_mode);
+        let motion = Motion {
+            channel_locations: channel_locations.as_array().to_owned(),
+            displacement: displacement.as_array().to_owned(),
+            temporal_bins: temporal_bins.as_array().to_owned(),
+            window_centers: window_centers.as_array().to_owned(),
+            sampling_frequency,
+        };
+        assert_eq!(motion.channel_locations.nrows(), self.num_channels, "channel_locations must have one row per channel");
+        assert_eq!(motion.displacement.dim(), (motion.temporal_bins.len(), motion.window_centers.len()), "displacement must be (n_temporal_bins, n_windows)");
+        assert!(!motion.temporal_bins.is_empty() && !motion.window_centers.is_empty(), "the motion needs at least one temporal bin and one window");
+        self.stages.push(Stage::MotionCorrection { motion, params });
+    }
+
+    #[pyo3(signature = (peak_sign, abs_thresholds, exclude_sweep_size, neighbours_mask, merge_both_signs=false, channel_mask=None, bad_channels=None))]
+    #[allow(clippy::too_many_arguments)]
+    pub fn set_detection(&mut self, peak_sign: &str, abs_thresholds: PyReadonlyArray1<f32>, exclude_sweep_size: usize, neighbours_mask: PyReadonlyArray2<bool>,
+            merge_both_signs: bool, channel_mask: Option<PyReadonlyArray1<bool>>, bad_channels: Option<Vec<usize>>) {
+        assert!(["pos", "neg", "both"].contains(&peak_sign), "peak_sign must be 'pos', 'neg', or 'both'");
+
+        let mut abs_thresholds: Array1<f32> = abs_thresholds.as_array().to_owned();
+        let mut adjency_list: Vec<Vec<usize>> = neighbours_mask_to_adjency_list(&neighbours_mask.as_array());
+        if let Some(enabled) = enabled_channels(self.num_channels, channel_mask.as_ref().map(|mask| mask.as_array()), bad_channels.as_deref()) {
+            exclude_channels(&mut abs_thresholds, &mut adjency_list, &enabled);
+        }
+
+        self.detection = Some(DetectionStage { peak_sign: peak_sign.to_string(), abs_thresholds, exclude_sweep_size, adjency_list, merge_both_signs });
+    }
+
+    #[pyo3(signature = (channel_locations, method="center_of_mass", radius_um=75.0, max_distance_um=150.0, nbefore=20, nafter=20, feature="ptp"))]
+    #[allow(clippy::too_many_arguments)]
+    pub fn set_localization(&mut self, channel_locations: PyReadonlyArray2<f32>, method: &str, radius_um: f32, max_distance_um: f32, nbefore: usize,
+            nafter: usize, feature: &str) {
+        assert!(["center_of_mass", "monopolar_triangulation"].contains(&method), "method must be 'center_of_mass' or 'monopolar_triangulation'");
+        assert!(FEATURES.contains(&feature), "feature must be 'ptp', 'mean', 'energy' or 'v_peak'");
+        let channel_locations: Array2<f32> = channel_locations.as_array().to_owned();
+        assert_eq!(channel_locations.nrows(), self.num_channels, "channel_locations must have one row per channel");
+
+        let local_channels = radius_adjency_list(&channel_locations.view(), radius_um);
+        let params = LocalizationParams { nbefore, nafter, feature: feature.to_string() };
+        self.localization = Some(LocalizationStage { method: method.to_string(), max_distance_um, channel_locations, local_channels, params });
+    }
+
+    // Components fitted by fit_temporal_svd_rust, (n_components, n_samples) with the peak at nbefore.
+    pub fn set_svd_features(&mut self, components: PyReadonlyArray2<f32>, nbefore: usize, neighbours_mask: PyReadonlyArray2<bool>) {
+        let components: Array2<f32> = components.as_array().to_owned();
+        assert!(nbefore < components.ncols(), "nbefore must be smaller than the number of samples of the components");
+        let neighbours_mask = neighbours_mask.as_array();
+        assert_eq!(neighbours_mask.dim(), (self.num_channels, self.num_channels), "neighbours_mask must be (num_channels, num_channels)");
+        let adjency_list = neighbours_mask_to_adjency_list(&neighbours_mask);
+        self.svd_features = Some(SvdFeaturesStage { components, nbefore, adjency_list });
+    }
+
+    pub fn get_trace_margin(&self) -> usize {
+        self.preprocessing_margin() + self.peak_margin()
+    }
+
+    // Preprocessed traces, without the margins consumed by the preprocessing stages.
+    #[pyo3(signature = (traces, start_sample=None))]
+    pub fn preprocess_on_chunk<'py>(&self, py: Python<'py>, traces: PyReadonlyArray2<f32>, start_sample: Option<usize>) -> Bound<'py,PyArray2<f32>> {
+        let start_sample = self.start_sample(start_sample);
+        let traces: ArrayView2<f32> = traces.as_array();
+        assert_eq!(traces.ncols(), self.num_channels, "traces must have num_channels columns");
+        assert!(traces.nrows() >= 2 * self.preprocessing_margin(), "the chunk is shorter than its margins");
+
+        let preprocessed: Array2<f32> = py.detach(|| {self.preprocess(&traces, start_sample)});
+        preprocessed.into_pyarray(py)
+    }
+
+    // Sample indices are relative to the input chunk, margins included, like detect_peaks_rust_locally_exclusive_on_chunk.
+    #[pyo3(signature = (traces, start_sample=None))]
+    pub fn run_on_chunk<'py>(&self, py: Python<'py>, traces: PyReadonlyArray2<f32>, start_sample: Option<usize>)
+            -> (Bound<'py,PyArray1<usize>>, Bound<'py,PyArray1<usize>>) {
+        let start_sample = self.start_sample(start_sample);
+        let detection = self.detection.as_ref().expect("set_detection() must be called before running the pipeline");
+        let traces: ArrayView2<f32> = traces.as_array();
+        assert_eq!(traces.ncols(), self.num_channels, "traces must have num_channels columns");
+        assert!(traces.nrows() >= 2 * self.get_trace_margin(), "the chunk is shorter than its margins");
+
+        let peaks: (Vec<usize>, Vec<usize>) = py.detach(|| {
+            let preprocessed = self.preprocess(&traces, start_sample);
+            let (mut sample_indices, channel_indices) = self.detect(&preprocessed.view(), detection);
+            let offset = self.preprocessing_margin();
+            sample_indices.iter_mut().for_each(|sample_ind| *sample_ind += offset);
+            (sample_indices, channel_indices)
+        });
+
+        (peaks.0.into_pyarray(py), peaks.1.into_pyarray(py))
+    }
+
+    #[pyo3(signature = (traces, start_sample=None))]
+    #[allow(clippy::type_complexity)]
+    pub fn run_and_localize_on_chunk<'py>(&self, py: Python<'py>, traces: PyReadonlyArray2<f32>, start_sample: Option<usize>)
+            -> (Bound<'py,PyArray1<usize>>, Bound<'py,PyArray1<usize>>, Bound<'py,PyArray1<f32>>, Bound<'py,PyArray1<f32>>) {
+        let start_sample = self.start_sample(start_sample);
+        let detection = self.detection.as_ref().expect("set_detection() must be called before running the pipeline");
+        let localization = self.localization.as_ref().expect("set_localization() must be called before localizing peaks");
+        let traces: ArrayView2<f32> = traces.as_array();
+        assert_eq!(traces.ncols(), self.num_channels, "traces must have num_channels columns");
+        assert!(traces.nrows() >= 2 * self.get_trace_margin(), "the chunk is shorter than its margins");
+
+        let (peaks, locations) = py.detach(|| {
+            let preprocessed = self.preprocess(&traces, start_sample);
+            let (mut sample_indices, channel_indices) = self.detect(&preprocessed.view(), detection);
+            let locations = self.localize(&preprocessed.view(), &ArrayView1::from(&sample_indices), &ArrayView1::from(&channel_indices), localization);
+            let offset = self.preprocessing_margin();
+            sample_indices.iter_mut().for_each(|sample_ind| *sample_ind += offset);
+            ((sample_indices, channel_indices), locations)
+        });
+
+        (peaks.0.into_pyarray(py), peaks.1.into_pyarray(py), locations.0.into_pyarray(py), locations.1.into_pyarray(py))
+    }
+
+    // Features are (n_peaks, n_components, n_local_channels), projected from the same preprocessed chunk as the
+    // detection, like extract_svd_features_rust_on_chunk.
+    #[pyo3(signature = (traces, start_sample=None))]
+    #[allow(clippy::type_complexity)]
+    pub fn run_and_extract_svd_features_on_chunk<'py>(&self, py: Python<'py>, traces: PyReadonlyArray2<f32>, start_sample: Option<usize>)
+            -> (Bound<'py,PyArray1<usize>>, Bound<'py,PyArray1<usize>>, Bound<'py,PyArray3<f32>>) {
+        let start_sample = self.start_sample(start_sample);
+        let detection = self.detection.as_ref().expect("set_detection() must be called before running the pipeline");
+        let svd_features = self.svd_features.as_ref().expect("set_svd_features() must be called before extracting features");
+        let traces: ArrayView2<f32> = traces.as_array();
+        assert_eq!(traces.ncols(), self.num_channels, "traces must have num_channels columns");
+        assert!(traces.nrows() >= 2 * self.get_trace_margin(), "the chunk is shorter than its margins");
+
+        let (peaks, features) = py.detach(|| {
+            let preprocessed = self.preprocess(&traces, start_sample);
+            let (mut sample_indices, channel_indices) = self.detect(&preprocessed.view(), detection);
+            let features: Array3<f32> = extract_svd_features(&preprocessed.view(), &ArrayView1::from(&sample_indices),
+                &ArrayView1::from(&channel_indices), &svd_features.adjency_list, svd_features.nbefore, &svd_features.components.view());
+            let offset = self.preprocessing_margin();
+            sample_indices.iter_mut().for_each(|sample_ind| *sample_ind += offset);
+            ((sample_indices, channel_indices), features)
+        });
+
+        (peaks.0.into_pyarray(py), peaks.1.into_pyarray(py), features.into_pyarray(py))
+    }
+}
+
+impl PeakDetectionPipeline {
+    // Peaks are only kept in [peak_margin, n - peak_margin) so that consecutive chunks do not report them twice; the
+    // detection itself already ignores its exclude_sweep_size first and last samples.
+    fn detect(&self, preprocessed: &ArrayView2<f32>, detection: &DetectionStage) -> (Vec<usize>, Vec<usize>) {
+        let trim = self.peak_margin() - detection.exclude_sweep_size;
+        let n_samples = preprocessed.nrows();
+        let (mut sample_indices, channel_indices) = detect_peaks_locally_exclusive(&preprocessed.slice(s![trim..n_samples - trim, ..]),
+            &detection.peak_sign, &detection.abs_thresholds.view(), detection.exclude_sweep_size, &detection.adjency_list, detection.merge_both_signs);
+        sample_indices.iter_mut().for_each(|sample_ind| *sample_ind += trim);
+        (sample_indices, channel_indices)
+    }
+
+    fn localize(&self, preprocessed: &ArrayView2<f32>, sample_indices: &ArrayView1<usize>, channel_indices: &ArrayView1<usize>,
+        localization: &LocalizationStage) -> (Vec<f32>, Vec<f32>) {
+        let channel_locations = localization.channel_locations.view();
+        match localization.method.as_str() {
+            "monopolar_triangulation" => localize_peaks_monopolar_triangulation(preprocessed, sample_indices, channel_indices, &channel_locations,
+                    &localization.local_channels, &localization.params, localization.max_distance_um)
+                .into_iter()
+                .map(|location| (location[0], location[1]))
+                .unzip(),
+            _ => localize_peaks_center_of_mass(preprocessed, sample_indices, channel_indices, &channel_locations, &localization.local_channels,
+                &localization.params),
+        }
+    }
+
+    fn start_sample(&self, start_sample: Option<usize>) -> usize {
+        let corrects_motion = self.stages.iter().any(|stage| matches!(stage, Stage::MotionCorrection { .. }));
+        assert!(start_sample.is_some() || !corrects_motion, "start_sample must be given when the pipeline corrects the motion");
+        start_sample.unwrap_or(0)
+    }
+
+    fn preprocessing_margin(&self) -> usize {
+        self.stages.iter().map(Stage::margin).sum()
+    }
+
+    // Margin left around the detected peaks once the preprocessing margins are consumed.
+    fn peak_margin(&self) -> usize {
+        let detection_margin = self.detection.as_ref().map_or(0, |detection| detection.exclude_sweep_size);
+        let localization_margin = self.localization.as_ref().map_or(0, |localization| localization.params.nbefore.max(localization.params.nafter));
+        let svd_features_margin = self.svd_features.as_ref()
+            .map_or(0, |svd_features| svd_features.nbefore.max(svd_features.components.ncols() - svd_features.nbefore));
+        detection_margin.max(localization_margin).max(svd_features_margin)
+    }
+
+    pub(crate) fn preprocess(&self, traces: &ArrayView2<f32>, start_sample: usize) -> Array2<f32> {
+        let mut data: Array2<f32> = traces.to_owned();
+        let mut start = 0;
+        let mut end = data.nrows();
+
+        for stage in &self.stages {
+            let mut chunk = data.slice_mut(s![start..end, ..]);
+            match stage {
+                Stage::BandpassFilter { sos, zero_phase, .. } => filter_traces_in_place(&mut chunk, sos, *zero_phase),
+                Stage::CommonReference { reference_channels, operator } => common_reference_in_place(&mut chunk, reference_channels, operator),
+                Stage::PhaseShift { sample_shifts, margin } => phase_shift_in_place(&mut chunk, &sample_shifts.view(), *margin),
+                Stage::Whitening { whitening_matrix, mean } => {
+                    let whitened = apply_whitening(&chunk.view(), &whitening_matrix.view(), &mean.view());
+                    chunk.assign(&whitened);
+                }
+                Stage::MotionCorrection { motion, params } => {
+                    let corrected = interpolate_motion(&chunk.view(), start_sample + start, motion.sampling_frequency, &motion.channel_locations.view(),
+                        &motion.displacement.view(), &motion.temporal_bins.view(), &motion.window_centers.view(), params);
+                    chunk.assign(&corrected);
+                }
+            }
+            start += stage.margin();
+            end -= stage.margin();
+        }
+
+        data.slice(s![start..end, ..]).to_owned()
+    }
+}
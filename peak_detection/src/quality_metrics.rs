@@ -0,0 +1,236 @@
+use ndarray::{Array1, Array2, ArrayView1, ArrayView2, ArrayView3, Axis};
+use ndarray::parallel::prelude::*;
+use numpy::{IntoPyArray, PyReadonlyArray1, PyReadonlyArray2, PyReadonlyArray3};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+pub(crate) const METRIC_NAMES: [&str; 8] = [
+    "num_spikes", "firing_rate", "isi_violations_ratio", "isi_violations_count", "presence_ratio", "amplitude_cutoff", "snr", "silhouette",
+];
+
+pub(crate) struct MetricsParams {
+    pub num_samples: usize,
+    pub sampling_frequency: f64,
+    pub isi_threshold_ms: f64,
+    pub min_isi_ms: f64,
+    pub presence_ratio_bin_duration_s: f64,
+    pub amplitude_cutoff_num_bins: usize,
+    pub amplitude_cutoff_smoothing: f64,
+    pub amplitude_cutoff_min_ratio: usize,
+}
+
+// Per-unit quality metrics following spikeinterface's definitions, returned as {metric name: array over units}. Metrics
+// that can not be computed for a unit (too few spikes, missing templates, noise levels or features) are NaN.
+// - isi_violations_ratio: Hill et al. rate of refractory period violations relative to the firing rate,
+// - presence_ratio: fraction of presence_ratio_bin_duration_s bins with at least one spike,
+// - amplitude_cutoff: Hill et al. estimate of the fraction of spikes missed below the detection threshold, from the
+//   smoothed histogram of the absolute amplitudes,
+// - snr: extremum of the template over the noise level (MAD, see get_noise_levels_rust) of its channel,
+// - silhouette: simplified silhouette (distances to the unit centroids) of the spike features.
+#[pyfunction]
+#[pyo3(signature = (sample_indices, unit_indices, amplitudes, num_units, num_samples, sampling_frequency, templates=None, noise_levels=None,
+    features=None, isi_threshold_ms=1.5, min_isi_ms=0.0, presence_ratio_bin_duration_s=60.0, amplitude_cutoff_num_bins=100,
+    amplitude_cutoff_smoothing=3.0, amplitude_cutoff_min_ratio=5))]
+#[allow(clippy::too_many_arguments)]
+pub fn compute_quality_metrics_rust<'py>(py: Python<'py>, sample_indices: PyReadonlyArray1<usize>, unit_indices: PyReadonlyArray1<usize>,
+            amplitudes: PyReadonlyArray1<f32>, num_units: usize, num_samples: usize, sampling_frequency: f64,
+            templates: Option<PyReadonlyArray3<f32>>, noise_levels: Option<PyReadonlyArray1<f32>>, features: Option<PyReadonlyArray2<f32>>,
+            isi_threshold_ms: f64, min_isi_ms: f64, presence_ratio_bin_duration_s: f64, amplitude_cutoff_num_bins: usize,
+            amplitude_cutoff_smoothing: f64, amplitude_cutoff_min_ratio: usize) -> PyResult<Bound<'py,PyDict>> {
+    let sample_indices: ArrayView1<usize> = sample_indices.as_array();
+    let unit_indices: ArrayView1<usize> = unit_indices.as_array();
+    let amplitudes: ArrayView1<f32> = amplitudes.as_array();
+    assert!(sample_indices.len() == unit_indices.len() && unit_indices.len() == amplitudes.len(), "sample_indices, unit_indices and amplitudes must have the same length");
+    assert!(unit_indices.iter().all(|&unit| unit < num_units), "unit_indices must be below num_units");
+    let templates: Option<ArrayView3<f32>> = templates.as_ref().map(|templates| templates.as_array());
+    let noise_levels: Option<ArrayView1<f32>> = noise_levels.as_ref().map(|noise_levels| noise_levels.as_array());
+    let features: Option<ArrayView2<f32>> = features.as_ref().map(|features| features.as_array());
+    if let Some(templates) = &templates {
+        assert_eq!(templates.len_of(Axis(0)), num_units, "templates must have one entry per unit");
+    }
+    if let Some(features) = &features {
+        assert_eq!(features.nrows(), sample_indices.len(), "features must have one row per spike");
+    }
+
+    let params = MetricsParams {
+        num_samples, sampling_frequency, isi_threshold_ms, min_isi_ms, presence_ratio_bin_duration_s, amplitude_cutoff_num_bins,
+        amplitude_cutoff_smoothing, amplitude_cutoff_min_ratio,
+    };
+
+    let metrics: Array2<f64> = py.detach(|| {
+        compute_quality_metrics(&sample_indices, &unit_indices, &amplitudes, num_units, templates.as_ref(), noise_levels.as_ref(), features.as_ref(), &params)
+    });
+
+    let table = PyDict::new(py);
+    for (name, column) in METRIC_NAMES.iter().zip(metrics.axis_iter(Axis(1))) {
+        table.set_item(name, column.to_owned().into_pyarray(py))?;
+    }
+    Ok(table)
+}
+
+// (num_units, METRIC_NAMES.len()) table, computed in parallel over units.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn compute_quality_metrics(sample_indices: &ArrayView1<usize>, unit_indices: &ArrayView1<usize>, amplitudes: &ArrayView1<f32>, num_units: usize,
+    templates: Option<&ArrayView3<f32>>, noise_levels: Option<&ArrayView1<f32>>, features: Option<&ArrayView2<f32>>, params: &MetricsParams) -> Array2<f64> {
+    let mut spikes_by_unit: Vec<Vec<usize>> = vec![Vec::new(); num_units];
+    for (i, &unit) in unit_indices.iter().enumerate() {
+        spikes_by_unit[unit].push(i);
+    }
+
+    let centroids: Option<Array2<f64>> = features.map(|features| {
+        let mut centroids: Array2<f64> = Array2::from_elem((num_units, features.ncols()), f64::NAN);
+        for (unit, spikes) in spikes_by_unit.iter().enumerate().filter(|(_, spikes)| !spikes.is_empty()) {
+            let sum = spikes.iter().fold(Array1::<f64>::zeros(features.ncols()), |sum, &i| sum + features.row(i).mapv(|value| value as f64));
+            centroids.row_mut(unit).assign(&(sum / spikes.len() as f64));
+        }
+        centroids
+    });
+
+    let rows: Vec<[f64; METRIC_NAMES.len()]> = spikes_by_unit.par_iter().enumerate()
+        .map(|(unit, spikes)| {
+            // the spikes do not need to be sorted in time
+            let mut spike_times: Vec<usize> = spikes.iter().map(|&i| sample_indices[i]).collect();
+            spike_times.sort_unstable();
+            let unit_amplitudes: Vec<f32> = spikes.iter().map(|&i| amplitudes[i]).collect();
+            let (isi_violations_ratio, isi_violations_count) = isi_violations(&spike_times, params);
+            let snr = match (templates, noise_levels) {
+                (Some(templates), Some(noise_levels)) => snr(&templates.index_axis(Axis(0), unit), noise_levels),
+                _ => f64::NAN,
+            };
+            let silhouette = match (features, &centroids) {
+                (Some(features), Some(centroids)) => simplified_silhouette(features, spikes, unit, centroids),
+                _ => f64::NAN,
+            };
+            [
+                spikes.len() as f64,
+                spikes.len() as f64 / duration_s(params),
+                isi_violations_ratio,
+                isi_violations_count,
+                presence_ratio(&spike_times, params),
+                amplitude_cutoff(&unit_amplitudes, params),
+                snr,
+                silhouette,
+            ]
+        })
+        .collect();
+
+    let mut metrics: Array2<f64> = Array2::zeros((num_units, METRIC_NAMES.len()));
+    for (mut row, values) in metrics.outer_iter_mut().zip(rows.iter()) {
+        row.assign(&ArrayView1::from(values));
+    }
+    metrics
+}
+
+fn duration_s(params: &MetricsParams) -> f64 {
+    params.num_samples as f64 / params.sampling_frequency
+}
+
+fn isi_violations(spike_times: &[usize], params: &MetricsParams) -> (f64, f64) {
+    let isi_threshold = params.isi_threshold_ms / 1000.0 * params.sampling_frequency;
+    let min_isi = params.min_isi_ms / 1000.0 * params.sampling_frequency;
+    let num_spikes = spike_times.len() as f64;
+
+    let num_violations = spike_times.windows(2).filter(|pair| ((pair[1] - pair[0]) as f64) < isi_threshold).count() as f64;
+    let violation_time = 2.0 * num_spikes * (isi_threshold - min_isi) / params.sampling_frequency;
+    let total_rate = num_spikes / duration_s(params);
+    if num_spikes == 0.0 || violation_time <= 0.0 {
+        return (f64::NAN, num_violations);
+    }
+    (num_violations / violation_time / total_rate, num_violations)
+}
+
+fn presence_ratio(spike_times: &[usize], params: &MetricsParams) -> f64 {
+    let bin_size = params.presence_ratio_bin_duration_s * params.sampling_frequency;
+    let num_bins = (params.num_samples as f64 / bin_size).ceil() as usize;
+    if num_bins == 0 {
+        return f64::NAN;
+    }
+    let mut present: Vec<bool> = vec![false; num_bins];
+    for &sample_ind in spike_times {
+        present[((sample_ind as f64 / bin_size) as usize).min(num_bins - 1)] = true;
+    }
+    present.iter().filter(|&&is_present| is_present).count() as f64 / num_bins as f64
+}
+
+fn amplitude_cutoff(amplitudes: &[f32], params: &MetricsParams) -> f64 {
+    let num_bins = params.amplitude_cutoff_num_bins;
+    if num_bins == 0 || amplitudes.len() < num_bins * params.amplitude_cutoff_min_ratio {
+        return f64::NAN;
+    }
+
+    let amplitudes: Vec<f64> = amplitudes.iter().map(|&amplitude| amplitude.abs() as f64).collect();
+    let low = amplitudes.iter().copied().fold(f64::INFINITY, f64::min);
+    let high = amplitudes.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    let bin_size = (high - low) / num_bins as f64;
+    if bin_size <= 0.0 {
+        return f64::NAN;
+    }
+
+    // density histogram, smoothed with a gaussian filter
+    let mut pdf: Vec<f64> = vec![0.0; num_bins];
+    for &amplitude in &amplitudes {
+        pdf[(((amplitude - low) / bin_size) as usize).min(num_bins - 1)] += 1.0;
+    }
+    for value in pdf.iter_mut() {
+        *value /= amplitudes.len() as f64 * bin_size;
+    }
+    let pdf = gaussian_smooth(&pdf, params.amplitude_cutoff_smoothing);
+
+    // the part of the distribution above the point symmetric to the lowest amplitude is missing below the threshold
+    let peak_ind = (0..num_bins).max_by(|&a, &b| pdf[a].total_cmp(&pdf[b])).unwrap_or(0);
+    let symmetric_ind = (peak_ind..num_bins).min_by(|&a, &b| (pdf[a] - pdf[0]).abs().total_cmp(&(pdf[b] - pdf[0]).abs())).unwrap_or(peak_ind);
+    let fraction_missing: f64 = pdf[symmetric_ind..].iter().sum::<f64>() * bin_size;
+    fraction_missing.min(0.5)
+}
+
+// Gaussian filter with reflected edges, truncated at 4 sigma like scipy.ndimage.gaussian_filter1d.
+fn gaussian_smooth(values: &[f64], sigma: f64) -> Vec<f64> {
+    if sigma <= 0.0 {
+        return values.to_vec();
+    }
+    let n = values.len() as isize;
+    let radius = (4.0 * sigma + 0.5) as isize;
+    let weights: Vec<f64> = (-radius..=radius).map(|k| (-0.5 * (k as f64 / sigma).powi(2)).exp()).collect();
+    let total: f64 = weights.iter().sum();
+
+    (0..n)
+        .map(|i| {
+            (-radius..=radius).zip(weights.iter())
+                .map(|(k, &weight)| {
+                    let mut j = i + k;
+                    while j < 0 || j >= n {
+                        j = if j < 0 { -j - 1 } else { 2 * n - j - 1 };
+                    }
+                    weight * values[j as usize]
+                })
+                .sum::<f64>() / total
+        })
+        .collect()
+}
+
+fn snr(template: &ArrayView2<f32>, noise_levels: &ArrayView1<f32>) -> f64 {
+    let extremum = template.indexed_iter().max_by(|a, b| a.1.abs().total_cmp(&b.1.abs()));
+    match extremum {
+        Some(((_, ch), &value)) if noise_levels[ch] > 0.0 => (value.abs() / noise_levels[ch]) as f64,
+        _ => f64::NAN,
+    }
+}
+
+fn simplified_silhouette(features: &ArrayView2<f32>, spikes: &[usize], unit: usize, centroids: &Array2<f64>) -> f64 {
+    let other_units: Vec<usize> = (0..centroids.nrows()).filter(|&other| other != unit && !centroids.row(other).iter().any(|value| value.is_nan())).collect();
+    if spikes.is_empty() || other_units.is_empty() {
+        return f64::NAN;
+    }
+    let distance = |i: usize, centroid: usize| -> f64 {
+        features.row(i).iter().zip(centroids.row(centroid).iter()).map(|(&a, &b)| (a as f64 - b).powi(2)).sum::<f64>().sqrt()
+    };
+
+    let total: f64 = spikes.iter()
+        .map(|&i| {
+            let a = distance(i, unit);
+            let b = other_units.iter().map(|&other| distance(i, other)).fold(f64::INFINITY, f64::min);
+            if a.max(b) > 0.0 { (b - a) / a.max(b) } else { 0.0 }
+        })
+        .sum();
+    total / spikes.len() as f64
+}
@@ -21,7 +21,7 @@ pub fn detect_peaks_rust_locally_exclusive_on_chunk<'py>(py: Python<'py>, traces
     (peaks.0.into_pyarray(py), peaks.1.into_pyarray(py))
 }
 
-fn detect_peaks_locally_exclusive(data : &ArrayView2<f32>, peak_sign: &str, abs_thresholds: &ArrayView1<f32>, exclude_sweep_size: usize, adjency_list: &Vec<Vec<usize>>) -> (Vec<usize>, Vec<usize>) {
+pub fn detect_peaks_locally_exclusive(data : &ArrayView2<f32>, peak_sign: &str, abs_thresholds: &ArrayView1<f32>, exclude_sweep_size: usize, adjency_list: &[Vec<usize>]) -> (Vec<usize>, Vec<usize>) {
 
     let n_samples = data.nrows();
     if n_samples == 0 {
@@ -37,14 +37,9 @@ fn detect_peaks_locally_exclusive(data : &ArrayView2<f32>, peak_sign: &str, abs_
     if ["pos","both"].contains(&peak_sign) {
         // Create the peak mask by comparing each value to the threshold for its channel
         for ((i, j), &value) in data_center.indexed_iter() {
-            if value > abs_thresholds[j] {
-                peak_mask[[i, j]] = true;
-            }
-            else {
-                peak_mask[[i, j]] = false;
-            }
+            peak_mask[[i, j]] = value > abs_thresholds[j];
         }
-        remove_neighboring_peaks(&mut peak_mask, &data,&data_center, &adjency_list, exclude_sweep_size,"pos");
+        remove_neighboring_peaks(&mut peak_mask, data,&data_center, adjency_list, exclude_sweep_size,"pos");
     }
 
     if ["neg","both"].contains(&peak_sign) {
@@ -54,14 +49,9 @@ fn detect_peaks_locally_exclusive(data : &ArrayView2<f32>, peak_sign: &str, abs_
         }
 
         for ((i, j), &value) in data_center.indexed_iter() {
-            if value < -abs_thresholds[j] {
-                peak_mask[[i, j]] = true;
-            }
-            else {
-                peak_mask[[i, j]] = false;
-            }
+            peak_mask[[i, j]] = value < -abs_thresholds[j];
         }
-        remove_neighboring_peaks(&mut peak_mask, &data,&data_center, &adjency_list, exclude_sweep_size,"neg");
+        remove_neighboring_peaks(&mut peak_mask, data,&data_center, adjency_list, exclude_sweep_size,"neg");
 
         if peak_sign == "both" {
             peak_mask = peak_mask | peak_mask_pos;
@@ -76,7 +66,7 @@ fn detect_peaks_locally_exclusive(data : &ArrayView2<f32>, peak_sign: &str, abs_
 }
 
 
-fn remove_neighboring_peaks(result_peak_mask: &mut Array2<bool>, data: &ArrayView2<f32>, data_center: &ArrayView2<f32>, adjency_list: &Vec<Vec<usize>>, exclude_sweep_size: usize, peak_sign: &str) {
+fn remove_neighboring_peaks(result_peak_mask: &mut Array2<bool>, data: &ArrayView2<f32>, data_center: &ArrayView2<f32>, adjency_list: &[Vec<usize>], exclude_sweep_size: usize, peak_sign: &str) {
     assert!(["pos", "neg"].contains(&peak_sign), "peak_sign must be 'pos' or 'neg'");
 
     if peak_sign == "pos" {
@@ -13,12 +13,12 @@ pub fn detect_peaks_rust_locally_exclusive_on_chunk<'py>(py: Python<'py>, traces
     let neighbours_mask: ArrayView2<bool> = neighbours_mask.as_array();
 
     let peaks: (Vec<usize>, Vec<usize>) = py.detach(|| {detect_peaks_locally_exclusive(&traces, peak_sign, &abs_thresholds, exclude_sweep_size, &neighbours_mask)});
-    return (peaks.0.into_pyarray(py), peaks.1.into_pyarray(py));
+    (peaks.0.into_pyarray(py), peaks.1.into_pyarray(py))
 
 }
 
 
-fn detect_peaks_locally_exclusive(traces : &ArrayView2<f32>, peak_sign: &str, abs_thresholds: &ArrayView1<f32>,
+pub fn detect_peaks_locally_exclusive(traces : &ArrayView2<f32>, peak_sign: &str, abs_thresholds: &ArrayView1<f32>,
     exclude_sweep_size: usize, neighbours_mask: &ArrayView2<bool>) -> (Vec<usize>, Vec<usize>) {
 
     use ndarray::s;
@@ -57,7 +57,7 @@ fn detect_peaks_locally_exclusive(traces : &ArrayView2<f32>, peak_sign: &str, ab
             }
         }
 
-        remove_neighboring_peaks_neg(&mut peak_mask, &traces, &traces_center, &abs_thresholds, exclude_sweep_size, &neighbours_mask);
+        remove_neighboring_peaks_neg(&mut peak_mask, traces, &traces_center, abs_thresholds, exclude_sweep_size, neighbours_mask);
 
         if peak_sign == "both" {
             peak_mask = peak_mask | peak_mask_pos;
@@ -15,12 +15,12 @@ pub fn detect_peaks_rust_locally_exclusive_on_chunk<'py>(py: Python<'py>, traces
     let peaks: (Vec<usize>, Vec<usize>) = py.detach(
         || {detect_peaks_locally_exclusive(&traces, peak_sign, &abs_thresholds, exclude_sweep_size, &neighbours_mask)}
     );
-    return (peaks.0.into_pyarray(py), peaks.1.into_pyarray(py));
+    (peaks.0.into_pyarray(py), peaks.1.into_pyarray(py))
 }
 
 
 
-fn detect_peaks_locally_exclusive(traces : &ArrayView2<f32>, peak_sign: &str, abs_thresholds: &ArrayView1<f32>,
+pub fn detect_peaks_locally_exclusive(traces : &ArrayView2<f32>, peak_sign: &str, abs_thresholds: &ArrayView1<f32>,
     exclude_sweep_size: usize, neighbours_mask: &ArrayView2<bool>) -> (Vec<usize>, Vec<usize>) {
 
     // use ndarray::s;
@@ -33,10 +33,6 @@ fn detect_peaks_locally_exclusive(traces : &ArrayView2<f32>, peak_sign: &str, ab
 
     // let traces_center = traces.slice(s![exclude_sweep_size..n_samples-exclude_sweep_size, ..]);
 
-    if ["pos","both"].contains(&peak_sign) {
-
-    }
-
     if ["neg","both"].contains(&peak_sign) {
 
         let peaks: (Vec<usize>, Vec<usize>) = traces.indexed_iter()
@@ -57,11 +53,12 @@ fn detect_peaks_locally_exclusive(traces : &ArrayView2<f32>, peak_sign: &str, ab
                 continue;
             }
 
+            // next_start only moves the start of the search for the next peaks
+            #[allow(clippy::mut_range_bound)]
             for j in next_start..npeaks{
                 if i == j {continue;}
                 
                 if (peaks.0[i]  + exclude_sweep_size ) < peaks.0[j] {
-                    //  println!("break {}", j);
                     break;
                 }
                 if (peaks.0[i]  - exclude_sweep_size ) > peaks.0[j]{
@@ -96,5 +93,5 @@ fn detect_peaks_locally_exclusive(traces : &ArrayView2<f32>, peak_sign: &str, ab
     }
 
 
-    return (vec![], vec![]);
+    (vec![], vec![])
 }
@@ -5,25 +5,65 @@ use numpy::{IntoPyArray, PyArray1, PyReadonlyArray1, PyReadonlyArray2};
 use pyo3::prelude::*;
 
 #[pyfunction]
-pub fn detect_peaks_rust_locally_exclusive_on_chunk<'py>(py: Python<'py>, traces: PyReadonlyArray2<f32>, peak_sign: &str, abs_thresholds: PyReadonlyArray1<f32>, exclude_sweep_size: usize, neighbours_mask: PyReadonlyArray2<bool>) -> (Bound<'py,PyArray1<usize>>, Bound<'py,PyArray1<usize>>) {
+#[pyo3(signature = (traces, peak_sign, abs_thresholds, exclude_sweep_size, neighbours_mask, merge_both_signs=false, channel_mask=None, bad_channels=None))]
+#[allow(clippy::too_many_arguments)]
+pub fn detect_peaks_rust_locally_exclusive_on_chunk<'py>(py: Python<'py>, traces: PyReadonlyArray2<f32>, peak_sign: &str, abs_thresholds: PyReadonlyArray1<f32>, exclude_sweep_size: usize, neighbours_mask: PyReadonlyArray2<bool>, merge_both_signs: bool, channel_mask: Option<PyReadonlyArray1<bool>>, bad_channels: Option<Vec<usize>>) -> (Bound<'py,PyArray1<usize>>, Bound<'py,PyArray1<usize>>) {
         assert!(["pos", "neg", "both"].contains(&peak_sign), "peak_sign must be 'pos', 'neg', or 'both'");
 
         let data: ArrayView2<f32> = traces.as_array();
-        let abs_thresholds: ArrayView1<f32> = abs_thresholds.as_array();
+        let mut abs_thresholds: Array1<f32> = abs_thresholds.as_array().to_owned();
         let neighbours_mask: ArrayView2<bool> = neighbours_mask.as_array();
-        let adjency_list: Vec<Vec<usize>> = neighbours_mask.axis_iter(ndarray::Axis(0))
-            .map(|row| row.indexed_iter()
-                .filter_map(|(j, &is_neighbor)| if is_neighbor { Some(j) } else { None })
-                .collect()
-            )
-            .collect();
+        let mut adjency_list: Vec<Vec<usize>> = neighbours_mask_to_adjency_list(&neighbours_mask);
 
-        let peaks: (Vec<usize>, Vec<usize>) = py.detach(|| {detect_peaks_locally_exclusive(&data, peak_sign, &abs_thresholds, exclude_sweep_size, &adjency_list)});
+        if let Some(enabled) = enabled_channels(data.ncols(), channel_mask.as_ref().map(|mask| mask.as_array()), bad_channels.as_deref()) {
+            exclude_channels(&mut abs_thresholds, &mut adjency_list, &enabled);
+        }
+
+        let peaks: (Vec<usize>, Vec<usize>) = py.detach(|| {detect_peaks_locally_exclusive(&data, peak_sign, &abs_thresholds.view(), exclude_sweep_size, &adjency_list, merge_both_signs)});
 
         (peaks.0.into_pyarray(py), peaks.1.into_pyarray(py))
 }
 
-fn detect_peaks_locally_exclusive(data : &ArrayView2<f32>, peak_sign: &str, abs_thresholds: &ArrayView1<f32>, exclude_sweep_size: usize, adjency_list: &[Vec<usize>]) -> (Vec<usize>, Vec<usize>) {
+pub(crate) fn enabled_channels(n_channels: usize, channel_mask: Option<ArrayView1<bool>>, bad_channels: Option<&[usize]>) -> Option<Vec<bool>> {
+    if channel_mask.is_none() && bad_channels.is_none() {
+        return None;
+    }
+    let mut enabled: Vec<bool> = match channel_mask {
+        Some(mask) => mask.to_vec(),
+        None => vec![true; n_channels],
+    };
+    assert_eq!(enabled.len(), n_channels, "channel_mask must have one entry per channel");
+    for &ch in bad_channels.into_iter().flatten() {
+        assert!(ch < n_channels, "bad_channels must be channel indices below {n_channels}");
+        enabled[ch] = false;
+    }
+    Some(enabled)
+}
+
+// Disabled channels get an infinite threshold so they never enter the sliding windows, and are removed from every
+// neighbourhood so they cannot suppress their neighbours. Channel numbering is left untouched.
+pub(crate) fn exclude_channels(abs_thresholds: &mut Array1<f32>, adjency_list: &mut [Vec<usize>], enabled: &[bool]) {
+    for (ch, neighbours) in adjency_list.iter_mut().enumerate() {
+        if enabled[ch] {
+            neighbours.retain(|&neighbour| enabled[neighbour]);
+        }
+        else {
+            abs_thresholds[ch] = f32::INFINITY;
+            neighbours.clear();
+        }
+    }
+}
+
+pub fn neighbours_mask_to_adjency_list(neighbours_mask: &ArrayView2<bool>) -> Vec<Vec<usize>> {
+    neighbours_mask.axis_iter(ndarray::Axis(0))
+        .map(|row| row.indexed_iter()
+            .filter_map(|(j, &is_neighbor)| if is_neighbor { Some(j) } else { None })
+            .collect()
+        )
+        .collect()
+}
+
+pub fn detect_peaks_locally_exclusive(data : &ArrayView2<f32>, peak_sign: &str, abs_thresholds: &ArrayView1<f32>, exclude_sweep_size: usize, adjency_list: &[Vec<usize>], merge_both_signs: bool) -> (Vec<usize>, Vec<usize>) {
 
     let n_samples = data.nrows();
     let n_channels = data.ncols();
@@ -130,16 +170,11 @@ fn detect_peaks_locally_exclusive(data : &ArrayView2<f32>, peak_sign: &str, abs_
             }
         }
 
+        // only the front of a deque can still be a peak: the samples behind it are smaller and within the sweep
         for i in 0..n_channels {
             let deque: &mut VecDeque<usize> = &mut current_max[i];
-            while !deque.is_empty() {
-                let last =deque.pop_front().unwrap();
-                if possible_peak[i] && last >= exclude_sweep_size && last < n_samples - exclude_sweep_size {
-                    peak_mask[[last - exclude_sweep_size, i]] = true;
-                }
-                else {
-                    break;
-                }
+            if let Some(last) = deque.pop_front() && possible_peak[i] && last >= exclude_sweep_size && last < n_samples - exclude_sweep_size {
+                peak_mask[[last - exclude_sweep_size, i]] = true;
             }
         }
     }
@@ -147,7 +182,7 @@ fn detect_peaks_locally_exclusive(data : &ArrayView2<f32>, peak_sign: &str, abs_
     if ["neg","both"].contains(&peak_sign) {
         let mut peak_mask_pos: Array2<bool> = Array2::from_elem((n_samples_center, data.ncols()), false);
         if peak_sign == "both" {
-            peak_mask_pos = peak_mask.clone();
+            peak_mask_pos = std::mem::replace(&mut peak_mask, Array2::from_elem((n_samples_center, n_channels), false));
         }
 
         // Create the peak mask by comparing each value to the threshold for its channel
@@ -242,20 +277,18 @@ fn detect_peaks_locally_exclusive(data : &ArrayView2<f32>, peak_sign: &str, abs_
             }
         }
 
+        // only the front of a deque can still be a peak: the samples behind it are smaller and within the sweep
         for i in 0..n_channels {
             let deque: &mut VecDeque<usize> = &mut current_min[i];
-            while !deque.is_empty() {
-                let last =deque.pop_front().unwrap();
-                if possible_peak[i] && last >= exclude_sweep_size && last < n_samples - exclude_sweep_size {
-                    peak_mask[[last - exclude_sweep_size, i]] = true;
-                }
-                else {
-                    break;
-                }
+            if let Some(last) = deque.pop_front() && possible_peak[i] && last >= exclude_sweep_size && last < n_samples - exclude_sweep_size {
+                peak_mask[[last - exclude_sweep_size, i]] = true;
             }
         }
 
         if peak_sign == "both" {
+            if merge_both_signs {
+                merge_opposite_sign_peaks(&mut peak_mask_pos, &mut peak_mask, data, abs_thresholds, exclude_sweep_size, adjency_list);
+            }
             peak_mask = peak_mask | peak_mask_pos;
         }
     }
@@ -265,4 +298,145 @@ fn detect_peaks_locally_exclusive(data : &ArrayView2<f32>, peak_sign: &str, abs_
         .unzip();
 
     result
-}
\ No newline at end of file
+}
+
+// With peak_sign="both", a positive and a negative peak falling in the same spatio-temporal neighbourhood compete:
+// only the one with the larger threshold-normalised magnitude is kept (ties go to the negative peak).
+fn merge_opposite_sign_peaks(peak_mask_pos: &mut Array2<bool>, peak_mask_neg: &mut Array2<bool>, data: &ArrayView2<f32>, abs_thresholds: &ArrayView1<f32>,
+    exclude_sweep_size: usize, adjency_list: &[Vec<usize>]) {
+    let n_samples_center = peak_mask_pos.nrows();
+    let magnitude = |i: usize, j: usize| data[[i + exclude_sweep_size, j]].abs() / abs_thresholds[j];
+
+    let mut pos_losers: Vec<(usize, usize)> = Vec::new();
+    let mut neg_losers: Vec<(usize, usize)> = Vec::new();
+
+    for ((i, j), &is_peak) in peak_mask_pos.indexed_iter() {
+        if !is_peak {
+            continue;
+        }
+        let pos_magnitude = magnitude(i, j);
+        let start = i.saturating_sub(exclude_sweep_size);
+        let end = (i + exclude_sweep_size + 1).min(n_samples_center);
+
+        let mut pos_lost = false;
+        for &ch in &adjency_list[j] {
+            for t in start..end {
+                if !peak_mask_neg[[t, ch]] {
+                    continue;
+                }
+                if magnitude(t, ch) >= pos_magnitude {
+                    pos_lost = true;
+                }
+                else {
+                    neg_losers.push((t, ch));
+                }
+            }
+        }
+        if pos_lost {
+            pos_losers.push((i, j));
+        }
+    }
+
+    for (i, j) in pos_losers {
+        peak_mask_pos[[i, j]] = false;
+    }
+    for (i, j) in neg_losers {
+        peak_mask_neg[[i, j]] = false;
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use crate::rust_peak_detection_locally_exclusive::detect_peaks_locally_exclusive as detect_peaks_mask;
+
+    // channels on a line, each one neighbouring the previous and next ones
+    fn line_adjency_list(n_channels: usize) -> Vec<Vec<usize>> {
+        (0..n_channels).map(|ch| (ch.saturating_sub(1)..(ch + 2).min(n_channels)).collect()).collect()
+    }
+
+    fn sorted_peaks(peaks: (Vec<usize>, Vec<usize>)) -> Vec<(usize, usize)> {
+        let mut peaks: Vec<(usize, usize)> = peaks.0.into_iter().zip(peaks.1).collect();
+        peaks.sort_unstable();
+        peaks
+    }
+
+    #[test]
+    fn without_merge_both_signs_matches_the_mask_detector() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let (n_samples, n_channels) = (2000, 6);
+        let mut data: Array2<f32> = Array2::from_shape_fn((n_samples, n_channels), |_| rng.random_range(-1.0..1.0));
+        // a last spike on each side of the probe followed by silence, so that nothing flushes the sliding windows
+        // before the end of the chunk
+        data.slice_mut(ndarray::s![n_samples - 100.., ..]).fill(0.0);
+        for k in 0..6 {
+            data[[n_samples - 90 + k, 0]] = -5.0 + 0.5 * k as f32;
+            data[[n_samples - 90 + k, n_channels - 1]] = 5.0 - 0.5 * k as f32;
+        }
+        let abs_thresholds: Array1<f32> = Array1::from_elem(n_channels, 0.9);
+        let adjency_list = line_adjency_list(n_channels);
+
+        for peak_sign in ["pos", "neg", "both"] {
+            let peaks = detect_peaks_locally_exclusive(&data.view(), peak_sign, &abs_thresholds.view(), 5, &adjency_list, false);
+            let expected = detect_peaks_mask(&data.view(), peak_sign, &abs_thresholds.view(), 5, &adjency_list);
+            assert!(!expected.0.is_empty(), "the fixture must have {peak_sign} peaks");
+            assert_eq!(sorted_peaks(peaks), sorted_peaks(expected), "peak_sign={peak_sign}");
+        }
+    }
+
+    #[test]
+    fn merge_both_signs_keeps_the_larger_extremum_of_a_biphasic_spike() {
+        let abs_thresholds: Array1<f32> = Array1::from_elem(1, 1.0);
+        let adjency_list = line_adjency_list(1);
+        for (trough, bump, expected) in [(-10.0, 6.0, 50), (-4.0, 6.0, 53)] {
+            let mut data: Array2<f32> = Array2::zeros((100, 1));
+            data[[50, 0]] = trough;
+            data[[53, 0]] = bump;
+
+            let unmerged = detect_peaks_locally_exclusive(&data.view(), "both", &abs_thresholds.view(), 5, &adjency_list, false);
+            assert_eq!(sorted_peaks(unmerged), vec![(50, 0), (53, 0)]);
+            let merged = detect_peaks_locally_exclusive(&data.view(), "both", &abs_thresholds.view(), 5, &adjency_list, true);
+            assert_eq!(sorted_peaks(merged), vec![(expected, 0)]);
+        }
+    }
+
+    #[test]
+    fn disabled_channels_neither_detect_nor_suppress() {
+        let mut abs_thresholds: Array1<f32> = Array1::from_elem(4, 1.0);
+        let mut adjency_list = line_adjency_list(4);
+        // channel 1 is the largest, and would suppress channel 2 when enabled
+        let mut data: Array2<f32> = Array2::zeros((100, 4));
+        data[[50, 1]] = -10.0;
+        data[[50, 2]] = -5.0;
+        data[[70, 3]] = -5.0;
+
+        let peaks = detect_peaks_locally_exclusive(&data.view(), "neg", &abs_thresholds.view(), 5, &adjency_list, false);
+        assert_eq!(sorted_peaks(peaks), vec![(50, 1), (70, 3)]);
+
+        let enabled = enabled_channels(4, None, Some(&[1])).unwrap();
+        exclude_channels(&mut abs_thresholds, &mut adjency_list, &enabled);
+        let peaks = detect_peaks_locally_exclusive(&data.view(), "neg", &abs_thresholds.view(), 5, &adjency_list, false);
+        // channel indices keep the original numbering
+        assert_eq!(sorted_peaks(peaks), vec![(50, 2), (70, 3)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "bad_channels must be channel indices below 4")]
+    fn out_of_range_bad_channels_are_rejected() {
+        enabled_channels(4, None, Some(&[4]));
+    }
+
+    #[test]
+    fn merge_both_signs_keeps_opposite_peaks_on_distant_channels() {
+        let abs_thresholds: Array1<f32> = Array1::from_elem(3, 1.0);
+        let adjency_list = line_adjency_list(3);
+        let mut data: Array2<f32> = Array2::zeros((100, 3));
+        data[[50, 0]] = -10.0;
+        data[[51, 2]] = 6.0;
+
+        let merged = detect_peaks_locally_exclusive(&data.view(), "both", &abs_thresholds.view(), 5, &adjency_list, true);
+        assert_eq!(sorted_peaks(merged), vec![(50, 0), (51, 2)]);
+    }
+}
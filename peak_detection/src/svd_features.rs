@@ -0,0 +1,120 @@
+use nalgebra::{DMatrix, SymmetricEigen};
+use ndarray::{Array1, Array2, Array3, ArrayView1, ArrayView2, ArrayView3, Axis};
+use ndarray::parallel::prelude::*;
+use numpy::{IntoPyArray, PyArray1, PyArray2, PyArray3, PyReadonlyArray1, PyReadonlyArray2, PyReadonlyArray3};
+use pyo3::prelude::*;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+use crate::rust_peak_detection_locally_exclusive_sliding_window::neighbours_mask_to_adjency_list;
+use crate::waveform_extraction::{extract_waveforms_into, max_local_channels};
+
+// Fits the temporal basis on the waveforms of a random subset of peaks (all of them when max_waveforms is None).
+// Every (peak, channel) snippet is one observation, as with spikeinterface's TruncatedSVD on
+// waveforms.swapaxes(1, 2).reshape(-1, n_samples); zero-padded channels do not change the basis.
+#[pyfunction]
+#[pyo3(signature = (waveforms, n_components, max_waveforms=None, seed=None))]
+pub fn fit_temporal_svd_rust<'py>(py: Python<'py>, waveforms: PyReadonlyArray3<f32>, n_components: usize, max_waveforms: Option<usize>,
+            seed: Option<u64>) -> (Bound<'py,PyArray2<f32>>, Bound<'py,PyArray1<f32>>) {
+    let waveforms: ArrayView3<f32> = waveforms.as_array();
+    assert!(n_components <= waveforms.len_of(Axis(1)), "n_components can not exceed the number of samples per waveform");
+
+    let (components, singular_values) = py.detach(|| {
+        let subset = random_subset(waveforms.len_of(Axis(0)), max_waveforms, seed);
+        fit_temporal_svd(&waveforms.select(Axis(0), &subset).view(), n_components)
+    });
+
+    (components.into_pyarray(py), singular_values.into_pyarray(py))
+}
+
+// Features are (n_peaks, n_components, n_local_channels), like spikeinterface's temporal_pca node.
+#[pyfunction]
+pub fn project_temporal_svd_rust<'py>(py: Python<'py>, waveforms: PyReadonlyArray3<f32>, components: PyReadonlyArray2<f32>) -> Bound<'py,PyArray3<f32>> {
+    let waveforms: ArrayView3<f32> = waveforms.as_array();
+    let components: ArrayView2<f32> = components.as_array();
+    assert_eq!(components.ncols(), waveforms.len_of(Axis(1)), "components must have as many samples as the waveforms");
+
+    let features: Array3<f32> = py.detach(|| project_temporal_svd(&waveforms, &components));
+
+    features.into_pyarray(py)
+}
+
+// Extracts the local waveform of every peak (see extract_waveforms_rust_on_chunk) and projects it on the components,
+// without returning the waveforms.
+#[pyfunction]
+pub fn extract_svd_features_rust_on_chunk<'py>(py: Python<'py>, traces: PyReadonlyArray2<f32>, sample_indices: PyReadonlyArray1<usize>,
+            channel_indices: PyReadonlyArray1<usize>, neighbours_mask: PyReadonlyArray2<bool>, nbefore: usize,
+            components: PyReadonlyArray2<f32>) -> Bound<'py,PyArray3<f32>> {
+    assert_eq!(sample_indices.len().unwrap(), channel_indices.len().unwrap(), "sample_indices and channel_indices must have the same length");
+
+    let traces: ArrayView2<f32> = traces.as_array();
+    let sample_indices: ArrayView1<usize> = sample_indices.as_array();
+    let channel_indices: ArrayView1<usize> = channel_indices.as_array();
+    let adjency_list: Vec<Vec<usize>> = neighbours_mask_to_adjency_list(&neighbours_mask.as_array());
+    let components: ArrayView2<f32> = components.as_array();
+
+    let features: Array3<f32> = py.detach(|| extract_svd_features(&traces, &sample_indices, &channel_indices, &adjency_list, nbefore, &components));
+
+    features.into_pyarray(py)
+}
+
+pub(crate) fn extract_svd_features(traces: &ArrayView2<f32>, sample_indices: &ArrayView1<usize>, channel_indices: &ArrayView1<usize>,
+    adjency_list: &[Vec<usize>], nbefore: usize, components: &ArrayView2<f32>) -> Array3<f32> {
+    let mut waveforms = Array3::zeros((sample_indices.len(), components.ncols(), max_local_channels(adjency_list)));
+    extract_waveforms_into(traces, sample_indices, channel_indices, adjency_list, nbefore, &mut waveforms.view_mut());
+    project_temporal_svd(&waveforms.view(), components)
+}
+
+pub(crate) fn random_subset(n: usize, max_size: Option<usize>, seed: Option<u64>) -> Vec<usize> {
+    match max_size {
+        Some(max_size) if max_size < n => {
+            let mut rng: StdRng = match seed {
+                Some(seed) => StdRng::seed_from_u64(seed),
+                None => StdRng::from_os_rng(),
+            };
+            let mut subset: Vec<usize> = rand::seq::index::sample(&mut rng, n, max_size).into_vec();
+            subset.sort_unstable();
+            subset
+        }
+        _ => (0..n).collect(),
+    }
+}
+
+// Right singular vectors of the (n_peaks * n_channels, n_samples) snippet matrix, obtained from the eigen decomposition
+// of its n_samples x n_samples Gram matrix, with the matching singular values in decreasing order.
+pub(crate) fn fit_temporal_svd(waveforms: &ArrayView3<f32>, n_components: usize) -> (Array2<f32>, Array1<f32>) {
+    let n_samples = waveforms.len_of(Axis(1));
+
+    let gram: Array2<f64> = waveforms.axis_iter(Axis(0)).into_par_iter()
+        .map(|waveform| {
+            let waveform: Array2<f64> = waveform.mapv(|value| value as f64);
+            waveform.dot(&waveform.t())
+        })
+        .reduce(|| Array2::zeros((n_samples, n_samples)), |a, b| a + b);
+
+    let eigen = SymmetricEigen::new(DMatrix::from_fn(n_samples, n_samples, |i, j| gram[[i, j]]));
+    let mut order: Vec<usize> = (0..n_samples).collect();
+    order.sort_by(|&a, &b| eigen.eigenvalues[b].total_cmp(&eigen.eigenvalues[a]));
+
+    let mut components: Array2<f32> = Array2::zeros((n_components, n_samples));
+    let mut singular_values: Array1<f32> = Array1::zeros(n_components);
+    for (k, &ind) in order.iter().take(n_components).enumerate() {
+        singular_values[k] = eigen.eigenvalues[ind].max(0.0).sqrt() as f32;
+        for t in 0..n_samples {
+            components[[k, t]] = eigen.eigenvectors[(t, ind)] as f32;
+        }
+    }
+
+    (components, singular_values)
+}
+
+pub(crate) fn project_temporal_svd(waveforms: &ArrayView3<f32>, components: &ArrayView2<f32>) -> Array3<f32> {
+    let (n_peaks, _, n_local_channels) = waveforms.dim();
+    let mut features: Array3<f32> = Array3::zeros((n_peaks, components.nrows(), n_local_channels));
+
+    features.axis_iter_mut(Axis(0)).into_par_iter().enumerate().for_each(|(i, mut feature)| {
+        feature.assign(&components.dot(&waveforms.index_axis(Axis(0), i)));
+    });
+
+    features
+}
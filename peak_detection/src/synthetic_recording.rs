@@ -0,0 +1,267 @@
+use std::f64::consts::PI;
+
+use nalgebra::DMatrix;
+use ndarray::{s, Array1, Array2, Array3, ArrayView1, ArrayView2, Axis};
+use ndarray::parallel::prelude::*;
+use numpy::{IntoPyArray, PyReadonlyArray2};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+pub struct SyntheticParams {
+    pub duration_s: f64,
+    pub sampling_frequency: f64,
+    pub num_units: usize,
+    pub firing_rate_range: (f64, f64),
+    pub refractory_period_ms: f64,
+    pub amplitude_range: (f32, f32),
+    pub amplitude_jitter: f32,
+    pub ms_before: f64,
+    pub ms_after: f64,
+    pub noise_level: f32,
+    pub noise_spatial_decay_um: f32,
+    pub noise_temporal_correlation: f32,
+    pub drift_amplitude_um: f32,
+    pub drift_period_s: f64,
+    pub seed: Option<u64>,
+}
+
+pub struct SyntheticRecording {
+    pub traces: Array2<f32>,
+    pub templates: Array3<f32>,
+    pub unit_locations: Array2<f32>,
+    pub spike_sample_indices: Vec<usize>,
+    pub spike_unit_indices: Vec<usize>,
+    pub spike_channel_indices: Vec<usize>,
+    pub spike_amplitudes: Vec<f32>,
+}
+
+// Synthetic traces for tests and benchmarks, returned as a dict with the traces, channel locations and ground truth:
+// - the probe is channel_locations, or a grid of num_channels contacts over num_columns columns,
+// - the noise is gaussian with noise_level std, spatially correlated as exp(-distance / noise_spatial_decay_um) and
+//   temporally colored by a first order autoregressive filter of coefficient noise_temporal_correlation,
+// - every unit is a monopolar current source at (x, y, z) in front of the probe, its template being a negative
+//   spike waveform scaled by 1 / distance, with a peak channel amplitude drawn in amplitude_range,
+// - spike trains are Poisson with a refractory period, each spike scaled by 1 + amplitude_jitter * N(0, 1),
+// - the units move along the depth by drift_amplitude_um * sin(2 pi t / drift_period_s).
+// Templates are given without drift; spike amplitudes (negative) and channels are those of each drifted spike.
+#[pyfunction]
+#[pyo3(signature = (duration_s=10.0, sampling_frequency=30000.0, num_units=10, channel_locations=None, num_channels=32, num_columns=2,
+    contact_pitch_um=20.0, firing_rate_range=(1.0, 15.0), refractory_period_ms=4.0, amplitude_range=(50.0, 200.0), amplitude_jitter=0.1,
+    ms_before=1.0, ms_after=2.0, noise_level=5.0, noise_spatial_decay_um=30.0, noise_temporal_correlation=0.5, drift_amplitude_um=0.0,
+    drift_period_s=30.0, seed=None))]
+#[allow(clippy::too_many_arguments)]
+pub fn generate_synthetic_recording_rust<'py>(py: Python<'py>, duration_s: f64, sampling_frequency: f64, num_units: usize,
+            channel_locations: Option<PyReadonlyArray2<f32>>, num_channels: usize, num_columns: usize, contact_pitch_um: f32,
+            firing_rate_range: (f64, f64), refractory_period_ms: f64, amplitude_range: (f32, f32), amplitude_jitter: f32, ms_before: f64,
+            ms_after: f64, noise_level: f32, noise_spatial_decay_um: f32, noise_temporal_correlation: f32, drift_amplitude_um: f32,
+            drift_period_s: f64, seed: Option<u64>) -> PyResult<Bound<'py,PyDict>> {
+    assert!((0.0..1.0).contains(&noise_temporal_correlation), "noise_temporal_correlation must be in [0, 1)");
+    assert!(firing_rate_range.0 <= firing_rate_range.1 && amplitude_range.0 <= amplitude_range.1, "ranges must be (low, high)");
+
+    let channel_locations: Array2<f32> = match channel_locations {
+        Some(channel_locations) => channel_locations.as_array().to_owned(),
+        None => grid_channel_locations(num_channels, num_columns, contact_pitch_um),
+    };
+    assert_eq!(channel_locations.ncols(), 2, "channel_locations must be (num_channels, 2)");
+
+    let params = SyntheticParams {
+        duration_s, sampling_frequency, num_units, firing_rate_range, refractory_period_ms, amplitude_range, amplitude_jitter, ms_before,
+        ms_after, noise_level, noise_spatial_decay_um, noise_temporal_correlation, drift_amplitude_um, drift_period_s, seed,
+    };
+
+    let recording = py.detach(|| generate_synthetic_recording(&channel_locations.view(), &params));
+
+    let results = PyDict::new(py);
+    results.set_item("traces", recording.traces.into_pyarray(py))?;
+    results.set_item("channel_locations", channel_locations.into_pyarray(py))?;
+    results.set_item("templates", recording.templates.into_pyarray(py))?;
+    results.set_item("nbefore", (ms_before * sampling_frequency / 1000.0) as usize)?;
+    results.set_item("unit_locations", recording.unit_locations.into_pyarray(py))?;
+    results.set_item("spike_sample_indices", recording.spike_sample_indices.into_pyarray(py))?;
+    results.set_item("spike_unit_indices", recording.spike_unit_indices.into_pyarray(py))?;
+    results.set_item("spike_channel_indices", recording.spike_channel_indices.into_pyarray(py))?;
+    results.set_item("spike_amplitudes", recording.spike_amplitudes.into_pyarray(py))?;
+    Ok(results)
+}
+
+// Contacts ordered by depth then column, num_columns per row, pitch_um apart in both directions.
+pub fn grid_channel_locations(num_channels: usize, num_columns: usize, pitch_um: f32) -> Array2<f32> {
+    let num_columns = num_columns.max(1);
+    Array2::from_shape_fn((num_channels, 2), |(ch, axis)| {
+        if axis == 0 { (ch % num_columns) as f32 * pitch_um } else { (ch / num_columns) as f32 * pitch_um }
+    })
+}
+
+pub fn generate_synthetic_recording(channel_locations: &ArrayView2<f32>, params: &SyntheticParams) -> SyntheticRecording {
+    let mut rng: StdRng = match params.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_os_rng(),
+    };
+    let n_samples = (params.duration_s * params.sampling_frequency) as usize;
+    let nbefore = (params.ms_before * params.sampling_frequency / 1000.0) as usize;
+    let nafter = (params.ms_after * params.sampling_frequency / 1000.0) as usize;
+
+    let mut traces = colored_noise(&mut rng, n_samples, channel_locations, params);
+
+    let waveform = spike_waveform(nbefore, nafter, params.sampling_frequency);
+    let unit_locations = random_unit_locations(&mut rng, channel_locations, params.num_units);
+    let unit_alphas: Vec<f32> = unit_locations.outer_iter()
+        .map(|location| {
+            let amplitude = rng.random_range(params.amplitude_range.0..=params.amplitude_range.1);
+            amplitude * min_distance(&location, channel_locations)
+        })
+        .collect();
+
+    let mut templates: Array3<f32> = Array3::zeros((params.num_units, nbefore + nafter, channel_locations.nrows()));
+    for (unit, mut template) in templates.outer_iter_mut().enumerate() {
+        template.assign(&monopolar_template(&waveform, &unit_locations.row(unit), unit_alphas[unit], channel_locations));
+    }
+
+    let mut spikes: Vec<(usize, usize)> = Vec::new();
+    for unit in 0..params.num_units {
+        let firing_rate = rng.random_range(params.firing_rate_range.0..=params.firing_rate_range.1);
+        spikes.extend(poisson_spike_train(&mut rng, firing_rate, nbefore, n_samples.saturating_sub(nafter), params).into_iter().map(|sample_ind| (sample_ind, unit)));
+    }
+    spikes.sort_unstable();
+
+    let mut spike_channel_indices: Vec<usize> = Vec::with_capacity(spikes.len());
+    let mut spike_amplitudes: Vec<f32> = Vec::with_capacity(spikes.len());
+    for &(sample_ind, unit) in &spikes {
+        let mut location = unit_locations.row(unit).to_owned();
+        location[1] += drift_at(sample_ind as f64 / params.sampling_frequency, params);
+        let scaling = (1.0 + params.amplitude_jitter * standard_normal(&mut rng) as f32).max(0.0);
+
+        let template = monopolar_template(&waveform, &location.view(), unit_alphas[unit] * scaling, channel_locations);
+        traces.slice_mut(s![sample_ind - nbefore..sample_ind + nafter, ..]).scaled_add(1.0, &template);
+
+        let peak_channel = (0..channel_locations.nrows())
+            .min_by(|&a, &b| distance(&location.view(), &channel_locations.row(a)).total_cmp(&distance(&location.view(), &channel_locations.row(b))))
+            .unwrap_or(0);
+        spike_channel_indices.push(peak_channel);
+        spike_amplitudes.push(template[[nbefore, peak_channel]]);
+    }
+
+    SyntheticRecording {
+        traces,
+        templates,
+        unit_locations,
+        spike_sample_indices: spikes.iter().map(|spike| spike.0).collect(),
+        spike_unit_indices: spikes.iter().map(|spike| spike.1).collect(),
+        spike_channel_indices,
+        spike_amplitudes,
+    }
+}
+
+fn standard_normal(rng: &mut StdRng) -> f64 {
+    // Box-Muller, 1 - u keeps the logarithm finite
+    let u: f64 = 1.0 - rng.random::<f64>();
+    let v: f64 = rng.random::<f64>();
+    (-2.0 * u.ln()).sqrt() * (2.0 * PI * v).cos()
+}
+
+// White noise filtered along time by an AR(1) process with unit variance, then mixed across channels by the Cholesky
+// factor of the spatial covariance.
+fn colored_noise(rng: &mut StdRng, n_samples: usize, channel_locations: &ArrayView2<f32>, params: &SyntheticParams) -> Array2<f32> {
+    let n_channels = channel_locations.nrows();
+    let mut noise: Array2<f32> = Array2::from_shape_simple_fn((n_samples, n_channels), || standard_normal(rng) as f32);
+
+    let rho = params.noise_temporal_correlation;
+    let innovation_scale = (1.0 - rho * rho).sqrt();
+    noise.axis_iter_mut(Axis(1)).into_par_iter().for_each(|mut trace| {
+        let mut previous = 0.0;
+        for value in trace.iter_mut() {
+            previous = rho * previous + innovation_scale * *value;
+            *value = previous;
+        }
+    });
+
+    let covariance = DMatrix::from_fn(n_channels, n_channels, |i, j| {
+        let d = distance(&channel_locations.row(i), &channel_locations.row(j)) as f64;
+        if params.noise_spatial_decay_um > 0.0 { (-d / params.noise_spatial_decay_um as f64).exp() } else if i == j { 1.0 } else { 0.0 }
+    });
+    // exp(-d / decay) is positive definite for distinct contacts
+    let factor = covariance.cholesky().map_or_else(|| DMatrix::identity(n_channels, n_channels), |cholesky| cholesky.l());
+    let mixing: Array2<f32> = Array2::from_shape_fn((n_channels, n_channels), |(i, j)| factor[(j, i)] as f32 * params.noise_level);
+
+    noise.dot(&mixing)
+}
+
+// Negative peak of unit amplitude at nbefore followed by a slower positive repolarization.
+fn spike_waveform(nbefore: usize, nafter: usize, sampling_frequency: f64) -> Array1<f32> {
+    let depolarization_ms = 0.2;
+    let repolarization_ms = 0.6;
+    let waveform: Array1<f64> = (0..nbefore + nafter)
+        .map(|k| {
+            let t_ms = (k as f64 - nbefore as f64) / sampling_frequency * 1000.0;
+            let negative = -(-0.5 * (t_ms / depolarization_ms).powi(2)).exp();
+            let positive = 0.3 * (-0.5 * ((t_ms - 3.0 * depolarization_ms) / repolarization_ms).powi(2)).exp();
+            negative + positive
+        })
+        .collect();
+    let peak = waveform.get(nbefore).map_or(1.0, |value| value.abs().max(f64::EPSILON));
+    waveform.mapv(|value| (value / peak) as f32)
+}
+
+// Units lie over the probe extent (plus 20 um on the sides), 5 to 50 um in front of it.
+fn random_unit_locations(rng: &mut StdRng, channel_locations: &ArrayView2<f32>, num_units: usize) -> Array2<f32> {
+    let bounds: Vec<(f32, f32)> = channel_locations.axis_iter(Axis(1))
+        .map(|axis| {
+            let low = axis.iter().copied().fold(f32::INFINITY, f32::min);
+            let high = axis.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+            (low - 20.0, high + 20.0)
+        })
+        .collect();
+    Array2::from_shape_fn((num_units, 3), |(_, axis)| {
+        let (low, high) = if axis < 2 { bounds[axis] } else { (5.0, 50.0) };
+        rng.random_range(low..=high)
+    })
+}
+
+fn monopolar_template(waveform: &Array1<f32>, location: &ArrayView1<f32>, alpha: f32, channel_locations: &ArrayView2<f32>) -> Array2<f32> {
+    let gains: Array1<f32> = channel_locations.outer_iter().map(|channel| alpha / distance(location, &channel)).collect();
+    let waveform = waveform.view().insert_axis(Axis(1));
+    let gains = gains.view().insert_axis(Axis(0));
+    waveform.dot(&gains)
+}
+
+// Euclidean distance over the common coordinates plus the remaining ones of the longer location (z for a unit).
+fn distance(a: &ArrayView1<f32>, b: &ArrayView1<f32>) -> f32 {
+    let common = a.len().min(b.len());
+    let in_plane: f32 = (0..common).map(|k| (a[k] - b[k]).powi(2)).sum();
+    let off_plane: f32 = a.iter().skip(common).chain(b.iter().skip(common)).map(|value| value * value).sum();
+    (in_plane + off_plane).sqrt()
+}
+
+fn min_distance(location: &ArrayView1<f32>, channel_locations: &ArrayView2<f32>) -> f32 {
+    channel_locations.outer_iter().map(|channel| distance(location, &channel)).fold(f32::INFINITY, f32::min)
+}
+
+fn drift_at(time_s: f64, params: &SyntheticParams) -> f32 {
+    if params.drift_amplitude_um == 0.0 || params.drift_period_s <= 0.0 {
+        return 0.0;
+    }
+    params.drift_amplitude_um * (2.0 * PI * time_s / params.drift_period_s).sin() as f32
+}
+
+// Spike samples in [first, last): exponential inter-spike intervals on top of the refractory period, the rate being
+// corrected so that the mean firing rate matches.
+fn poisson_spike_train(rng: &mut StdRng, firing_rate: f64, first: usize, last: usize, params: &SyntheticParams) -> Vec<usize> {
+    let refractory_s = params.refractory_period_ms / 1000.0;
+    let mean_interval_s = 1.0 / firing_rate.max(f64::EPSILON);
+    let exponential_mean_s = (mean_interval_s - refractory_s).max(f64::EPSILON);
+
+    let mut spike_train: Vec<usize> = Vec::new();
+    let mut time_s = first as f64 / params.sampling_frequency;
+    loop {
+        let u: f64 = 1.0 - rng.random::<f64>();
+        time_s += refractory_s - exponential_mean_s * u.ln();
+        let sample_ind = (time_s * params.sampling_frequency) as usize;
+        if sample_ind >= last {
+            break;
+        }
+        spike_train.push(sample_ind);
+    }
+    spike_train
+}
@@ -0,0 +1,155 @@
+use ndarray::{s, Array2, Array3, ArrayView1, ArrayView2, Axis};
+use ndarray::parallel::prelude::*;
+use numpy::{IntoPyArray, PyArray2, PyArray3, PyReadonlyArray1, PyReadonlyArray2};
+use pyo3::prelude::*;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::common_reference::median;
+
+pub(crate) struct UnitAccumulator {
+    sum: Array2<f64>,
+    count: usize,
+    // flattened (nbefore + nafter, num_channels) snippets, only kept for the median: a uniform random subset of at
+    // most max_spikes_per_unit snippets (reservoir sampling)
+    waveforms: Vec<f32>,
+    rng: StdRng,
+}
+
+// Templates accumulated chunk by chunk, fed with the same chunks (and margins) as the detection.
+#[pyclass]
+pub struct TemplateEstimator {
+    num_channels: usize,
+    nbefore: usize,
+    nafter: usize,
+    use_median: bool,
+    max_spikes_per_unit: usize,
+    units: Vec<UnitAccumulator>,
+}
+
+#[pymethods]
+impl TemplateEstimator {
+    // The median is computed over at most max_spikes_per_unit snippets per unit, drawn uniformly among all the spikes
+    // of the unit, which bounds the memory; the average uses every spike.
+    #[new]
+    #[pyo3(signature = (num_units, num_channels, nbefore, nafter, operator="average", max_spikes_per_unit=1000, seed=None))]
+    pub fn new(num_units: usize, num_channels: usize, nbefore: usize, nafter: usize, operator: &str, max_spikes_per_unit: usize,
+        seed: Option<u64>) -> Self {
+        assert!(["average", "median"].contains(&operator), "operator must be 'average' or 'median'");
+        assert!(max_spikes_per_unit > 0, "max_spikes_per_unit must be positive");
+        // one generator per unit, so that the units can be accumulated in parallel
+        let units = (0..num_units)
+            .map(|unit| {
+                let rng: StdRng = match seed {
+                    Some(seed) => StdRng::seed_from_u64(seed.wrapping_add(unit as u64)),
+                    None => StdRng::from_os_rng(),
+                };
+                UnitAccumulator { sum: Array2::zeros((nbefore + nafter, num_channels)), count: 0, waveforms: Vec::new(), rng }
+            })
+            .collect();
+        TemplateEstimator { num_channels, nbefore, nafter, use_median: operator == "median", max_spikes_per_unit, units }
+    }
+
+    // Sample indices are relative to the chunk, margins included. Peaks labelled -1 (noise) and peaks whose snippet
+    // does not fit in the chunk are skipped, the latter being expected in the margins of a neighbouring chunk.
+    pub fn add_chunk(&mut self, py: Python<'_>, traces: PyReadonlyArray2<f32>, sample_indices: PyReadonlyArray1<usize>, labels: PyReadonlyArray1<i64>) {
+        let traces: ArrayView2<f32> = traces.as_array();
+        let sample_indices: ArrayView1<usize> = sample_indices.as_array();
+        let labels: ArrayView1<i64> = labels.as_array();
+        assert_eq!(traces.ncols(), self.num_channels, "traces must have num_channels columns");
+        assert_eq!(sample_indices.len(), labels.len(), "sample_indices and labels must have the same length");
+        assert!(labels.iter().all(|&label| label < self.units.len() as i64), "labels must be below num_units");
+
+        py.detach(|| {
+            let mut peaks_by_unit: Vec<Vec<usize>> = vec![Vec::new(); self.units.len()];
+            for (&sample_ind, &label) in sample_indices.iter().zip(labels.iter()) {
+                if label >= 0 && sample_ind >= self.nbefore && sample_ind + self.nafter <= traces.nrows() {
+                    peaks_by_unit[label as usize].push(sample_ind);
+                }
+            }
+
+            let (nbefore, nafter, use_median, max_spikes_per_unit) = (self.nbefore, self.nafter, self.use_median, self.max_spikes_per_unit);
+            let snippet_size = (nbefore + nafter) * self.num_channels;
+            self.units.par_iter_mut().zip(peaks_by_unit.par_iter()).for_each(|(unit, peaks)| {
+                for &sample_ind in peaks {
+                    let snippet = traces.slice(s![sample_ind - nbefore..sample_ind + nafter, ..]);
+                    unit.sum.zip_mut_with(&snippet, |sum, &value| *sum += value as f64);
+                    if use_median {
+                        // the n-th spike replaces a kept snippet with probability max_spikes_per_unit / (n + 1)
+                        if unit.count < max_spikes_per_unit {
+                            unit.waveforms.extend(snippet.iter());
+                        } else {
+                            let slot = unit.rng.random_range(0..=unit.count);
+                            if slot < max_spikes_per_unit {
+                                let kept = &mut unit.waveforms[slot * snippet_size..(slot + 1) * snippet_size];
+                                kept.iter_mut().zip(snippet.iter()).for_each(|(kept, &value)| *kept = value);
+                            }
+                        }
+                    }
+                    unit.count += 1;
+                }
+            });
+        });
+    }
+
+    // Templates are (num_units, nbefore + nafter, num_channels), zero for units without spikes. A channel belongs to
+    // the sparsity of a unit when its peak-to-peak amplitude is at least sparsity_threshold times the one of the
+    // largest channel (every channel without threshold).
+    #[pyo3(signature = (sparsity_threshold=None))]
+    pub fn get_templates<'py>(&self, py: Python<'py>, sparsity_threshold: Option<f32>) -> (Bound<'py,PyArray3<f32>>, Bound<'py,PyArray2<bool>>) {
+        let n_samples = self.nbefore + self.nafter;
+
+        let (templates, sparsity_mask) = py.detach(|| {
+            let mut templates: Array3<f32> = Array3::zeros((self.units.len(), n_samples, self.num_channels));
+            templates.axis_iter_mut(Axis(0)).into_par_iter().enumerate().for_each(|(unit_ind, mut template)| {
+                let unit = &self.units[unit_ind];
+                if unit.count == 0 {
+                    return;
+                }
+                if self.use_median {
+                    let snippet_size = n_samples * self.num_channels;
+                    let mut values: Vec<f32> = Vec::with_capacity(unit.count.min(self.max_spikes_per_unit));
+                    for (k, value) in template.iter_mut().enumerate() {
+                        values.clear();
+                        values.extend(unit.waveforms.iter().skip(k).step_by(snippet_size));
+                        *value = median(&mut values);
+                    }
+                } else {
+                    template.zip_mut_with(&unit.sum, |value, &sum| *value = (sum / unit.count as f64) as f32);
+                }
+            });
+
+            let sparsity_mask = templates_sparsity(&templates, sparsity_threshold);
+            (templates, sparsity_mask)
+        });
+
+        (templates.into_pyarray(py), sparsity_mask.into_pyarray(py))
+    }
+
+    pub fn get_spike_counts(&self) -> Vec<usize> {
+        self.units.iter().map(|unit| unit.count).collect()
+    }
+}
+
+pub(crate) fn templates_sparsity(templates: &Array3<f32>, sparsity_threshold: Option<f32>) -> Array2<bool> {
+    let (n_units, _, n_channels) = templates.dim();
+    let Some(sparsity_threshold) = sparsity_threshold else {
+        return Array2::from_elem((n_units, n_channels), true);
+    };
+
+    let mut sparsity_mask: Array2<bool> = Array2::from_elem((n_units, n_channels), false);
+    for (template, mut mask) in templates.outer_iter().zip(sparsity_mask.outer_iter_mut()) {
+        let amplitudes: Vec<f32> = template.axis_iter(Axis(1))
+            .map(|trace| {
+                let max = trace.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+                let min = trace.iter().copied().fold(f32::INFINITY, f32::min);
+                max - min
+            })
+            .collect();
+        let max_amplitude = amplitudes.iter().copied().fold(0.0, f32::max);
+        for (selected, &amplitude) in mask.iter_mut().zip(amplitudes.iter()) {
+            *selected = max_amplitude > 0.0 && amplitude >= sparsity_threshold * max_amplitude;
+        }
+    }
+    sparsity_mask
+}
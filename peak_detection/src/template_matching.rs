@@ -0,0 +1,162 @@
+use std::collections::BTreeMap;
+
+use ndarray::{s, Array2, Array3, ArrayView1, ArrayView2, ArrayView3, Axis};
+use ndarray::parallel::prelude::*;
+use numpy::{IntoPyArray, PyArray1, PyReadonlyArray1, PyReadonlyArray2, PyReadonlyArray3};
+use pyo3::prelude::*;
+
+use crate::rust_peak_detection_locally_exclusive_sliding_window::{detect_peaks_locally_exclusive, neighbours_mask_to_adjency_list};
+
+pub(crate) struct SparseTemplates {
+    templates: Array3<f32>,
+    nbefore: usize,
+    // sparse channels and squared norm over them, per unit
+    channels: Vec<Vec<usize>>,
+    norms: Vec<f32>,
+    // units whose sparsity contains the channel, per channel
+    units_by_channel: Vec<Vec<usize>>,
+}
+
+pub(crate) struct PeelerParams {
+    pub peak_sign: String,
+    pub exclude_sweep_size: usize,
+    pub amplitude_limits: (f32, f32),
+    pub max_iterations: usize,
+}
+
+// Greedy peeler: each iteration proposes candidate times with the locally-exclusive detection on the residual, fits
+// the best template among the units whose sparsity contains the candidate channel (least-squares amplitude, largest
+// residual energy reduction) and subtracts the accepted spikes. As all the candidates of an iteration are fitted on the
+// same residual, a candidate is only accepted when no candidate with a larger gain overlaps it in time on a shared
+// sparse channel; the others are detected again on the next residual. Stops when no candidate is accepted.
+// Templates are (n_units, nbefore + nafter, n_channels), aligned on their peak at nbefore. Sample indices are relative
+// to the chunk, margins included.
+#[pyfunction]
+#[pyo3(signature = (traces, templates, sparsity_mask, nbefore, peak_sign, abs_thresholds, exclude_sweep_size, neighbours_mask,
+    amplitude_limits=(0.6, 2.0), max_iterations=10))]
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn template_matching_rust_on_chunk<'py>(py: Python<'py>, traces: PyReadonlyArray2<f32>, templates: PyReadonlyArray3<f32>,
+            sparsity_mask: PyReadonlyArray2<bool>, nbefore: usize, peak_sign: &str, abs_thresholds: PyReadonlyArray1<f32>,
+            exclude_sweep_size: usize, neighbours_mask: PyReadonlyArray2<bool>, amplitude_limits: (f32, f32),
+            max_iterations: usize) -> (Bound<'py,PyArray1<usize>>, Bound<'py,PyArray1<usize>>, Bound<'py,PyArray1<f32>>) {
+    assert!(["pos", "neg", "both"].contains(&peak_sign), "peak_sign must be 'pos', 'neg', or 'both'");
+
+    let traces: ArrayView2<f32> = traces.as_array();
+    let templates: ArrayView3<f32> = templates.as_array();
+    let sparsity_mask: ArrayView2<bool> = sparsity_mask.as_array();
+    let abs_thresholds: ArrayView1<f32> = abs_thresholds.as_array();
+    assert_eq!(templates.len_of(Axis(2)), traces.ncols(), "templates must have as many channels as the traces");
+    assert_eq!(sparsity_mask.dim(), (templates.len_of(Axis(0)), traces.ncols()), "sparsity_mask must be (n_units, n_channels)");
+    assert!(nbefore < templates.len_of(Axis(1)), "nbefore must be smaller than the template length");
+
+    let adjency_list: Vec<Vec<usize>> = neighbours_mask_to_adjency_list(&neighbours_mask.as_array());
+    let sparse_templates = SparseTemplates::new(&templates, &sparsity_mask, nbefore);
+    let params = PeelerParams { peak_sign: peak_sign.to_string(), exclude_sweep_size, amplitude_limits, max_iterations };
+
+    let spikes: (Vec<usize>, Vec<usize>, Vec<f32>) = py.detach(|| {
+        let mut residual: Array2<f32> = traces.to_owned();
+        peel_templates(&mut residual, &sparse_templates, &abs_thresholds, &adjency_list, &params)
+    });
+
+    (spikes.0.into_pyarray(py), spikes.1.into_pyarray(py), spikes.2.into_pyarray(py))
+}
+
+impl SparseTemplates {
+    pub(crate) fn new(templates: &ArrayView3<f32>, sparsity_mask: &ArrayView2<bool>, nbefore: usize) -> Self {
+        let channels: Vec<Vec<usize>> = sparsity_mask.outer_iter()
+            .map(|mask| mask.iter().enumerate().filter_map(|(ch, &selected)| if selected { Some(ch) } else { None }).collect())
+            .collect();
+        let norms: Vec<f32> = templates.outer_iter().zip(channels.iter())
+            .map(|(template, channels)| channels.iter().map(|&ch| template.column(ch).iter().map(|v| v * v).sum::<f32>()).sum())
+            .collect();
+        let mut units_by_channel: Vec<Vec<usize>> = vec![Vec::new(); sparsity_mask.ncols()];
+        for (unit, unit_channels) in channels.iter().enumerate() {
+            if norms[unit] > 0.0 {
+                for &ch in unit_channels {
+                    units_by_channel[ch].push(unit);
+                }
+            }
+        }
+        SparseTemplates { templates: templates.to_owned(), nbefore, channels, norms, units_by_channel }
+    }
+
+    fn nafter(&self) -> usize {
+        self.templates.len_of(Axis(1)) - self.nbefore
+    }
+
+    // Best (unit, amplitude, gain) at this peak, the amplitude being the least-squares scaling of the template.
+    fn best_match(&self, residual: &ArrayView2<f32>, sample_ind: usize, channel_ind: usize, amplitude_limits: (f32, f32)) -> Option<(usize, f32, f32)> {
+        let snippet = residual.slice(s![sample_ind - self.nbefore..sample_ind + self.nafter(), ..]);
+        self.units_by_channel[channel_ind].iter()
+            .filter_map(|&unit| {
+                let template = self.templates.index_axis(Axis(0), unit);
+                let dot: f32 = self.channels[unit].iter().map(|&ch| template.column(ch).dot(&snippet.column(ch))).sum();
+                let amplitude = dot / self.norms[unit];
+                // energy removed from the residual by subtracting amplitude * template
+                let gain = dot * amplitude;
+                (amplitude >= amplitude_limits.0 && amplitude <= amplitude_limits.1).then_some((unit, amplitude, gain))
+            })
+            .max_by(|a, b| a.2.total_cmp(&b.2))
+    }
+
+    // Whether the templates of both spikes overlap in time on at least one shared sparse channel.
+    fn overlap(&self, sample_a: usize, unit_a: usize, sample_b: usize, unit_b: usize) -> bool {
+        sample_a.abs_diff(sample_b) < self.templates.len_of(Axis(1))
+            && self.channels[unit_a].iter().any(|ch| self.channels[unit_b].binary_search(ch).is_ok())
+    }
+
+    fn subtract(&self, residual: &mut Array2<f32>, sample_ind: usize, unit: usize, amplitude: f32) {
+        let template = self.templates.index_axis(Axis(0), unit);
+        let mut snippet = residual.slice_mut(s![sample_ind - self.nbefore..sample_ind + self.nafter(), ..]);
+        for &ch in &self.channels[unit] {
+            snippet.column_mut(ch).scaled_add(-amplitude, &template.column(ch));
+        }
+    }
+}
+
+pub(crate) fn peel_templates(residual: &mut Array2<f32>, templates: &SparseTemplates, abs_thresholds: &ArrayView1<f32>, adjency_list: &[Vec<usize>],
+    params: &PeelerParams) -> (Vec<usize>, Vec<usize>, Vec<f32>) {
+    let n_samples = residual.nrows();
+    let mut spikes: Vec<(usize, usize, f32)> = Vec::new();
+
+    for _ in 0..params.max_iterations {
+        let (sample_indices, channel_indices) = detect_peaks_locally_exclusive(&residual.view(), &params.peak_sign, abs_thresholds,
+            params.exclude_sweep_size, adjency_list, false);
+
+        let mut candidates: Vec<(usize, usize, f32, f32)> = {
+            let residual = residual.view();
+            sample_indices.par_iter().zip(channel_indices.par_iter())
+                .filter(|&(&sample_ind, _)| sample_ind >= templates.nbefore && sample_ind + templates.nafter() <= n_samples)
+                .filter_map(|(&sample_ind, &channel_ind)| {
+                    templates.best_match(&residual, sample_ind, channel_ind, params.amplitude_limits)
+                        .map(|(unit, amplitude, gain)| (sample_ind, unit, amplitude, gain))
+                })
+                .collect()
+        };
+        if candidates.is_empty() {
+            break;
+        }
+
+        // largest gains first, a candidate overlapping an accepted spike waits for the next iteration
+        candidates.sort_by(|a, b| b.3.total_cmp(&a.3).then(a.0.cmp(&b.0)));
+        let mut accepted: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+        let mut matches: Vec<(usize, usize, f32)> = Vec::new();
+        let template_length = templates.templates.len_of(Axis(1));
+        for (sample_ind, unit, amplitude, _) in candidates {
+            let conflict = accepted.range(sample_ind.saturating_sub(template_length - 1)..sample_ind + template_length)
+                .any(|(&other_sample, other_units)| other_units.iter().any(|&other_unit| templates.overlap(sample_ind, unit, other_sample, other_unit)));
+            if !conflict {
+                accepted.entry(sample_ind).or_default().push(unit);
+                matches.push((sample_ind, unit, amplitude));
+            }
+        }
+
+        for &(sample_ind, unit, amplitude) in &matches {
+            templates.subtract(residual, sample_ind, unit, amplitude);
+        }
+        spikes.extend(matches);
+    }
+
+    spikes.sort_by_key(|&(sample_ind, unit, _)| (sample_ind, unit));
+    (spikes.iter().map(|spike| spike.0).collect(), spikes.iter().map(|spike| spike.1).collect(), spikes.iter().map(|spike| spike.2).collect())
+}
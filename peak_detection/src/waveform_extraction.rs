@@ -0,0 +1,81 @@
+use ndarray::{s, Array3, ArrayView1, ArrayView2, ArrayViewMut3, Axis};
+use ndarray::parallel::prelude::*;
+use numpy::{IntoPyArray, PyArray3, PyReadonlyArray1, PyReadonlyArray2, PyReadwriteArray3};
+use pyo3::prelude::*;
+
+use crate::rust_peak_detection_locally_exclusive_sliding_window::neighbours_mask_to_adjency_list;
+
+// Waveforms are (n_peaks, nbefore + nafter, n_local_channels): the local channels of a peak are the neighbours of its
+// channel in increasing channel order (np.flatnonzero(neighbours_mask[channel_index])), zero-padded up to the largest
+// neighbourhood. Samples falling outside the chunk are left at zero.
+#[pyfunction]
+pub fn extract_waveforms_rust_on_chunk<'py>(py: Python<'py>, traces: PyReadonlyArray2<f32>, sample_indices: PyReadonlyArray1<usize>,
+            channel_indices: PyReadonlyArray1<usize>, neighbours_mask: PyReadonlyArray2<bool>, nbefore: usize, nafter: usize) -> Bound<'py,PyArray3<f32>> {
+    assert_eq!(sample_indices.len().unwrap(), channel_indices.len().unwrap(), "sample_indices and channel_indices must have the same length");
+
+    let traces: ArrayView2<f32> = traces.as_array();
+    let sample_indices: ArrayView1<usize> = sample_indices.as_array();
+    let channel_indices: ArrayView1<usize> = channel_indices.as_array();
+    let adjency_list: Vec<Vec<usize>> = neighbours_mask_to_adjency_list(&neighbours_mask.as_array());
+
+    let waveforms: Array3<f32> = py.detach(|| {
+        let mut waveforms = Array3::zeros((sample_indices.len(), nbefore + nafter, max_local_channels(&adjency_list)));
+        extract_waveforms_into(&traces, &sample_indices, &channel_indices, &adjency_list, nbefore, &mut waveforms.view_mut());
+        waveforms
+    });
+
+    waveforms.into_pyarray(py)
+}
+
+// Same as extract_waveforms_rust_on_chunk, written into rows [offset, offset + n_peaks) of a preallocated
+// (possibly memory-mapped) output.
+#[pyfunction]
+#[pyo3(signature = (traces, sample_indices, channel_indices, neighbours_mask, nbefore, nafter, output, offset=0))]
+#[allow(clippy::too_many_arguments)]
+pub fn extract_waveforms_rust_into(py: Python<'_>, traces: PyReadonlyArray2<f32>, sample_indices: PyReadonlyArray1<usize>, channel_indices: PyReadonlyArray1<usize>,
+            neighbours_mask: PyReadonlyArray2<bool>, nbefore: usize, nafter: usize, mut output: PyReadwriteArray3<f32>, offset: usize) {
+    assert_eq!(sample_indices.len().unwrap(), channel_indices.len().unwrap(), "sample_indices and channel_indices must have the same length");
+
+    let traces: ArrayView2<f32> = traces.as_array();
+    let sample_indices: ArrayView1<usize> = sample_indices.as_array();
+    let channel_indices: ArrayView1<usize> = channel_indices.as_array();
+    let adjency_list: Vec<Vec<usize>> = neighbours_mask_to_adjency_list(&neighbours_mask.as_array());
+    let mut output: ArrayViewMut3<f32> = output.as_array_mut();
+
+    let (n_rows, n_samples, n_local_channels) = output.dim();
+    assert!(offset + sample_indices.len() <= n_rows, "output is too small for the peaks at this offset");
+    assert_eq!(n_samples, nbefore + nafter, "output must have nbefore + nafter samples");
+    assert!(n_local_channels >= max_local_channels(&adjency_list), "output has fewer channels than the largest neighbourhood");
+
+    py.detach(|| {
+        let mut rows = output.slice_mut(s![offset..offset + sample_indices.len(), .., ..]);
+        rows.fill(0.0);
+        extract_waveforms_into(&traces, &sample_indices, &channel_indices, &adjency_list, nbefore, &mut rows);
+    });
+}
+
+pub(crate) fn max_local_channels(adjency_list: &[Vec<usize>]) -> usize {
+    adjency_list.iter().map(Vec::len).max().unwrap_or(0)
+}
+
+// Expects a zero-initialised output, filled in parallel over peaks.
+pub(crate) fn extract_waveforms_into(traces: &ArrayView2<f32>, sample_indices: &ArrayView1<usize>, channel_indices: &ArrayView1<usize>,
+    adjency_list: &[Vec<usize>], nbefore: usize, waveforms: &mut ArrayViewMut3<f32>) {
+    let n_samples = traces.nrows();
+    let width = waveforms.len_of(Axis(1));
+
+    waveforms.axis_iter_mut(Axis(0)).into_par_iter().enumerate().for_each(|(i, mut waveform)| {
+        let sample_ind = sample_indices[i];
+        // part of the window [sample_ind - nbefore, sample_ind + nafter) inside the chunk
+        let first = nbefore.saturating_sub(sample_ind);
+        let last = width.min((n_samples + nbefore).saturating_sub(sample_ind));
+        if first >= last {
+            return;
+        }
+        let start = sample_ind + first - nbefore;
+
+        for (local_ind, &ch) in adjency_list[channel_indices[i]].iter().enumerate() {
+            waveform.slice_mut(s![first..last, local_ind]).assign(&traces.slice(s![start..start + last - first, ch]));
+        }
+    });
+}
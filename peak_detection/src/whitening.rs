@@ -0,0 +1,118 @@
+use nalgebra::{DMatrix, SymmetricEigen};
+use ndarray::{Array1, Array2, ArrayView1, ArrayView2, Axis};
+use numpy::{IntoPyArray, PyArray1, PyArray2, PyReadonlyArray1, PyReadonlyArray2};
+use pyo3::prelude::*;
+
+use crate::noise_levels::get_random_data_chunks;
+use crate::rust_peak_detection_locally_exclusive_sliding_window::{detect_peaks_locally_exclusive, neighbours_mask_to_adjency_list};
+
+#[pyfunction]
+#[pyo3(signature = (traces, mode="global", neighbours_mask=None, num_chunks=20, chunk_size=10000, seed=None, eps=1e-8, apply_mean=false))]
+#[allow(clippy::too_many_arguments)]
+pub fn compute_whitening_matrix_rust<'py>(py: Python<'py>, traces: PyReadonlyArray2<f32>, mode: &str, neighbours_mask: Option<PyReadonlyArray2<bool>>,
+            num_chunks: usize, chunk_size: usize, seed: Option<u64>, eps: f64, apply_mean: bool) -> (Bound<'py,PyArray2<f32>>, Bound<'py,PyArray1<f32>>) {
+    assert!(["global", "local"].contains(&mode), "mode must be 'global' or 'local'");
+
+    let traces: ArrayView2<f32> = traces.as_array();
+    let adjency_list: Option<Vec<Vec<usize>>> = if mode == "local" {
+        let neighbours_mask: ArrayView2<bool> = neighbours_mask.as_ref().expect("a local whitening needs neighbours_mask").as_array();
+        Some(neighbours_mask_to_adjency_list(&neighbours_mask))
+    } else {
+        None
+    };
+
+    let (whitening_matrix, mean) = py.detach(|| {
+        let chunks = get_random_data_chunks(&traces, num_chunks, chunk_size, seed);
+        compute_whitening_matrix(&chunks.view(), adjency_list.as_deref(), eps, apply_mean)
+    });
+
+    (whitening_matrix.into_pyarray(py), mean.into_pyarray(py))
+}
+
+#[pyfunction]
+pub fn whiten_rust_on_chunk<'py>(py: Python<'py>, traces: PyReadonlyArray2<f32>, whitening_matrix: PyReadonlyArray2<f32>,
+            mean: PyReadonlyArray1<f32>) -> Bound<'py,PyArray2<f32>> {
+    let traces: ArrayView2<f32> = traces.as_array();
+    let whitening_matrix: ArrayView2<f32> = whitening_matrix.as_array();
+    let mean: ArrayView1<f32> = mean.as_array();
+
+    let whitened: Array2<f32> = py.detach(|| {apply_whitening(&traces, &whitening_matrix, &mean)});
+
+    whitened.into_pyarray(py)
+}
+
+#[pyfunction]
+#[allow(clippy::too_many_arguments)]
+pub fn detect_peaks_rust_locally_exclusive_whitened_on_chunk<'py>(py: Python<'py>, traces: PyReadonlyArray2<f32>, whitening_matrix: PyReadonlyArray2<f32>,
+            mean: PyReadonlyArray1<f32>, peak_sign: &str, abs_thresholds: PyReadonlyArray1<f32>, exclude_sweep_size: usize,
+            neighbours_mask: PyReadonlyArray2<bool>) -> (Bound<'py,PyArray1<usize>>, Bound<'py,PyArray1<usize>>) {
+    assert!(["pos", "neg", "both"].contains(&peak_sign), "peak_sign must be 'pos', 'neg', or 'both'");
+
+    let traces: ArrayView2<f32> = traces.as_array();
+    let whitening_matrix: ArrayView2<f32> = whitening_matrix.as_array();
+    let mean: ArrayView1<f32> = mean.as_array();
+    let abs_thresholds: ArrayView1<f32> = abs_thresholds.as_array();
+    let neighbours_mask: ArrayView2<bool> = neighbours_mask.as_array();
+    let adjency_list: Vec<Vec<usize>> = neighbours_mask_to_adjency_list(&neighbours_mask);
+
+    let peaks: (Vec<usize>, Vec<usize>) = py.detach(|| {
+        let whitened = apply_whitening(&traces, &whitening_matrix, &mean);
+        detect_peaks_locally_exclusive(&whitened.view(), peak_sign, &abs_thresholds, exclude_sweep_size, &adjency_list, false)
+    });
+
+    (peaks.0.into_pyarray(py), peaks.1.into_pyarray(py))
+}
+
+// ZCA whitening matrix W (applied as traces @ W) from the channel covariance. With an adjency list, column c of W only
+// combines the neighbours of c, taken from the whitening of the neighbourhood covariance (spikeinterface "local" mode).
+pub(crate) fn compute_whitening_matrix(data: &ArrayView2<f32>, adjency_list: Option<&[Vec<usize>]>, eps: f64, apply_mean: bool) -> (Array2<f32>, Array1<f32>) {
+    let n_channels = data.ncols();
+    let n_samples = data.nrows().max(1);
+
+    let mut data: Array2<f64> = data.mapv(|value| value as f64);
+    let mean: Array1<f64> = if apply_mean {
+        data.mean_axis(Axis(0)).unwrap_or_else(|| Array1::zeros(n_channels))
+    } else {
+        Array1::zeros(n_channels)
+    };
+    data -= &mean;
+    let covariance: Array2<f64> = data.t().dot(&data) / n_samples as f64;
+
+    let mut whitening_matrix: Array2<f32> = Array2::zeros((n_channels, n_channels));
+    match adjency_list {
+        None => {
+            let all_channels: Vec<usize> = (0..n_channels).collect();
+            let w = zca_matrix(&covariance, &all_channels, eps);
+            for i in 0..n_channels {
+                for j in 0..n_channels {
+                    whitening_matrix[[i, j]] = w[(i, j)] as f32;
+                }
+            }
+        }
+        Some(adjency_list) => {
+            for (c, neighbours) in adjency_list.iter().enumerate() {
+                let Some(local_c) = neighbours.iter().position(|&ch| ch == c) else {
+                    continue;
+                };
+                let w = zca_matrix(&covariance, neighbours, eps);
+                for (local_k, &k) in neighbours.iter().enumerate() {
+                    whitening_matrix[[k, c]] = w[(local_k, local_c)] as f32;
+                }
+            }
+        }
+    }
+
+    (whitening_matrix, mean.mapv(|value| value as f32))
+}
+
+fn zca_matrix(covariance: &Array2<f64>, channels: &[usize], eps: f64) -> DMatrix<f64> {
+    let n = channels.len();
+    let sub_covariance = DMatrix::from_fn(n, n, |i, j| covariance[[channels[i], channels[j]]]);
+    let eigen = SymmetricEigen::new(sub_covariance);
+    let scaling = eigen.eigenvalues.map(|value| 1.0 / (value.max(0.0) + eps).sqrt());
+    &eigen.eigenvectors * DMatrix::from_diagonal(&scaling) * eigen.eigenvectors.transpose()
+}
+
+pub(crate) fn apply_whitening(traces: &ArrayView2<f32>, whitening_matrix: &ArrayView2<f32>, mean: &ArrayView1<f32>) -> Array2<f32> {
+    (traces - mean).dot(whitening_matrix)
+}